@@ -0,0 +1,78 @@
+#[path = "../tests/common/legacy_recolor.rs"]
+mod legacy_recolor;
+
+use carcaro::functionality::recolor::{apply_color_change, PaintTarget};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use legacy_recolor::legacy_apply_color_change;
+use opencv::core::{Mat, MatTraitConst, Point, Scalar, Size, CV_8UC1, CV_8UC3};
+use opencv::imgproc;
+
+const FRAMES: usize = 12;
+const TARGET_COLOR: [u8; 3] = [200, 30, 45];
+
+/// A 1080p frame of noise with an elliptical "car body" mask in the middle.
+fn synthetic_frame() -> (Mat, Mat) {
+    let mut image =
+        Mat::new_rows_cols_with_default(1080, 1920, CV_8UC3, Scalar::all(0.0)).unwrap();
+    opencv::core::randu(&mut image, &Scalar::all(0.0), &Scalar::all(255.0)).unwrap();
+
+    let mut mask =
+        Mat::new_rows_cols_with_default(1080, 1920, CV_8UC1, Scalar::all(0.0)).unwrap();
+    imgproc::ellipse(
+        &mut mask,
+        Point::new(960, 540),
+        Size::new(700, 300),
+        0.0,
+        0.0,
+        360.0,
+        Scalar::all(255.0),
+        imgproc::FILLED,
+        imgproc::LINE_8,
+        0,
+    )
+    .unwrap();
+    (image, mask)
+}
+
+fn frame_set(frame: &Mat) -> Vec<Mat> {
+    (0..FRAMES).map(|_| frame.try_clone().unwrap()).collect()
+}
+
+fn recolor_benchmark(c: &mut Criterion) {
+    let (frame, mask) = synthetic_frame();
+    let target = PaintTarget::from(TARGET_COLOR);
+
+    let mut group = c.benchmark_group("recolor_12_frames_1080p");
+    group.sample_size(10);
+
+    group.bench_function("legacy_at_2d", |b| {
+        b.iter_batched(
+            || frame_set(&frame),
+            |mut frames| {
+                for image in frames.iter_mut() {
+                    legacy_apply_color_change(image, &mask, TARGET_COLOR);
+                }
+                frames
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.bench_function("row_slices", |b| {
+        b.iter_batched(
+            || frame_set(&frame),
+            |mut frames| {
+                for image in frames.iter_mut() {
+                    apply_color_change(image, &mask, |_, _| target).unwrap();
+                }
+                frames
+            },
+            BatchSize::LargeInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, recolor_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS decal_placement (
+    placementid SERIAL PRIMARY KEY,
+    imageid INTEGER NOT NULL REFERENCES image (imageid) ON DELETE CASCADE,
+    frame INTEGER NOT NULL,
+    url TEXT NOT NULL,
+    x DOUBLE PRECISION NOT NULL,
+    y DOUBLE PRECISION NOT NULL,
+    scale DOUBLE PRECISION NOT NULL,
+    rotation DOUBLE PRECISION NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS decal_placement_imageid_idx ON decal_placement (imageid);
@@ -0,0 +1,6 @@
+ALTER TABLE image ADD COLUMN IF NOT EXISTS frame_count INTEGER;
+
+UPDATE image SET frame_count = cardinality(url) WHERE frame_count IS NULL;
+
+ALTER TABLE image ALTER COLUMN frame_count SET NOT NULL;
+ALTER TABLE image ADD CONSTRAINT image_frame_count_matches_url CHECK (frame_count = cardinality(url));
@@ -0,0 +1 @@
+ALTER TABLE image ADD COLUMN IF NOT EXISTS variants JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS animation (
+    animationid SERIAL PRIMARY KEY,
+    imageid INTEGER NOT NULL REFERENCES image (imageid) ON DELETE CASCADE,
+    format TEXT NOT NULL,
+    url TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    delay_ms INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS animation_imageid_idx ON animation (imageid);
@@ -0,0 +1 @@
+ALTER TABLE image ADD COLUMN IF NOT EXISTS sprite_sheet JSONB;
@@ -0,0 +1 @@
+ALTER TABLE image ADD COLUMN IF NOT EXISTS base_imageid INTEGER REFERENCES image (imageid) ON DELETE SET NULL;
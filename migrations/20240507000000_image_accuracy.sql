@@ -0,0 +1 @@
+ALTER TABLE image ADD COLUMN IF NOT EXISTS accuracy JSONB NOT NULL DEFAULT '[]';
@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS color_wall (
+    color_wallid SERIAL PRIMARY KEY,
+    imageid INTEGER NOT NULL REFERENCES image (imageid) ON DELETE CASCADE,
+    userid INTEGER,
+    status TEXT NOT NULL DEFAULT 'running',
+    total INTEGER NOT NULL,
+    completed INTEGER NOT NULL DEFAULT 0,
+    renders JSONB NOT NULL DEFAULT '[]'
+);
+
+CREATE INDEX IF NOT EXISTS color_wall_imageid_idx ON color_wall (imageid);
@@ -0,0 +1,44 @@
+-- Renders used to be stored as SAS URLs that expire after 30 days. Anything on
+-- the storage account is reduced to its `{container}/{blob}` key, which the API
+-- signs when it hands images out; URLs to other hosts are left alone.
+CREATE OR REPLACE FUNCTION pg_temp.storage_key(url TEXT) RETURNS TEXT AS $$
+    SELECT regexp_replace(url, '^https://wrapmycar\.blob\.core\.windows\.net/([^?]+)(\?.*)?$', '\1');
+$$ LANGUAGE SQL IMMUTABLE;
+
+UPDATE image
+SET url = ARRAY(
+    SELECT pg_temp.storage_key(frame_url)
+    FROM unnest(url) WITH ORDINALITY AS frames (frame_url, position)
+    ORDER BY position
+);
+
+UPDATE image
+SET variants = (
+    SELECT coalesce(jsonb_agg(
+        jsonb_set(frame, '{variants}', (
+            SELECT coalesce(jsonb_agg(
+                jsonb_set(variant, '{url}', to_jsonb(pg_temp.storage_key(variant ->> 'url')))
+                ORDER BY variant_position
+            ), '[]'::jsonb)
+            FROM jsonb_array_elements(frame -> 'variants') WITH ORDINALITY AS v (variant, variant_position)
+        ))
+        ORDER BY frame_position
+    ), '[]'::jsonb)
+    FROM jsonb_array_elements(variants) WITH ORDINALITY AS f (frame, frame_position)
+);
+
+UPDATE image
+SET sprite_sheet = jsonb_set(sprite_sheet, '{url}', to_jsonb(pg_temp.storage_key(sprite_sheet ->> 'url')))
+WHERE sprite_sheet IS NOT NULL;
+
+UPDATE color_wall
+SET renders = (
+    SELECT coalesce(jsonb_agg(
+        jsonb_set(entry, '{url}', (
+            SELECT coalesce(jsonb_agg(to_jsonb(pg_temp.storage_key(frame_url)) ORDER BY url_position), '[]'::jsonb)
+            FROM jsonb_array_elements_text(entry -> 'url') WITH ORDINALITY AS u (frame_url, url_position)
+        ))
+        ORDER BY entry_position
+    ), '[]'::jsonb)
+    FROM jsonb_array_elements(renders) WITH ORDINALITY AS e (entry, entry_position)
+);
@@ -0,0 +1,5 @@
+-- Rows that exist before this migration count as created now, so retention
+-- starts from the day it runs.
+ALTER TABLE image ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS image_anonymous_created_at_idx ON image (created_at) WHERE userid IS NULL;
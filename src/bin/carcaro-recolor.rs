@@ -0,0 +1,205 @@
+//! Recolors a local directory of base frames with the same masking and
+//! recolor logic as the server, without Postgres or Azure.
+//!
+//!     carcaro-recolor frames/ renders/ --color "RAL 3020" --color "#0A0A0A" --format webp
+
+use carcaro::functionality::accuracy::score_frame;
+use carcaro::functionality::color_swap::{desired_areas_with_profile, recolor_frame};
+use carcaro::functionality::encoding;
+use carcaro::functionality::mask::MaskProfile;
+use carcaro::types::color::{parse_hex, Color};
+use carcaro::types::finish::Finish;
+use carcaro::types::output::OutputFormat;
+use clap::Parser;
+use opencv::core::{Mat, MatTraitConst};
+use opencv::imgcodecs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const FRAME_EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "webp", "bmp", "tiff"];
+
+#[derive(Parser)]
+#[command(
+    name = "carcaro-recolor",
+    about = "Recolor a directory of car frames offline"
+)]
+struct Args {
+    /// Directory with the base frames
+    input: PathBuf,
+    /// Directory the renders are written to, one subdirectory per color
+    output: PathBuf,
+    /// Target color as `#RRGGBB`, `R,G,B` or a RAL code like `RAL 3020`; can be repeated
+    #[arg(short, long = "color", required = true)]
+    colors: Vec<String>,
+    /// RAL colors to look codes up in, as the JSON returned by `GET /colors`
+    #[arg(long, default_value = "colors.json")]
+    palette: PathBuf,
+    /// Range of base paint colors to mask: standard, wide or strict
+    #[arg(long, default_value = "standard", value_parser = parse_mask_profile)]
+    mask_profile: MaskProfile,
+    /// Output format: png, webp, jpeg or avif
+    #[arg(long, default_value = "png", value_parser = parse_format)]
+    format: OutputFormat,
+    /// Encoding quality from 1 to 100, ignored for PNG
+    #[arg(long, default_value_t = 90, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: u8,
+}
+
+fn parse_mask_profile(name: &str) -> Result<MaskProfile, String> {
+    MaskProfile::from_name(name).ok_or_else(|| format!("unknown mask profile `{}`", name))
+}
+
+fn parse_format(name: &str) -> Result<OutputFormat, String> {
+    OutputFormat::from_name(name).ok_or_else(|| format!("unknown output format `{}`", name))
+}
+
+struct Target {
+    /// Name of the output subdirectory
+    label: String,
+    rgb: [u8; 3],
+}
+
+/// RAL codes are stored both as `RAL 3020` and as `3020`, so both sides are
+/// compared without the prefix and whitespace.
+fn normalize_ral(code: &str) -> String {
+    let code = code.trim().to_uppercase();
+    code.trim_start_matches("RAL")
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect()
+}
+
+fn load_palette(path: &Path) -> Result<Vec<Color>, String> {
+    let data = std::fs::read(path)
+        .map_err(|e| format!("cannot read RAL palette {}: {}", path.display(), e))?;
+    serde_json::from_slice(&data)
+        .map_err(|e| format!("cannot parse RAL palette {}: {}", path.display(), e))
+}
+
+fn parse_target(
+    arg: &str,
+    palette: &mut Option<Vec<Color>>,
+    palette_path: &Path,
+) -> Result<Target, String> {
+    if arg.trim().to_uppercase().starts_with("RAL") {
+        if palette.is_none() {
+            *palette = Some(load_palette(palette_path)?);
+        }
+        let code = normalize_ral(arg);
+        let color = palette
+            .iter()
+            .flatten()
+            .find(|color| normalize_ral(&color.ral) == code)
+            .ok_or_else(|| format!("RAL {} is not in {}", code, palette_path.display()))?;
+        let rgb = color
+            .rgb()
+            .ok_or_else(|| format!("RAL {} has an invalid hex code `{}`", code, color.hex))?;
+        return Ok(Target {
+            label: format!("RAL_{}", code),
+            rgb,
+        });
+    }
+
+    let rgb = if arg.contains(',') {
+        let channels: Vec<u8> = arg
+            .split(',')
+            .map(|channel| channel.trim().parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("`{}` is not an R,G,B color", arg))?;
+        <[u8; 3]>::try_from(channels).map_err(|_| format!("`{}` is not an R,G,B color", arg))?
+    } else {
+        parse_hex(arg).ok_or_else(|| format!("`{}` is not a hex color", arg))?
+    };
+    Ok(Target {
+        label: format!("{:02X}{:02X}{:02X}", rgb[0], rgb[1], rgb[2]),
+        rgb,
+    })
+}
+
+fn frame_paths(input: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        std::fs::read_dir(input).map_err(|e| format!("cannot read {}: {}", input.display(), e))?;
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .map(|extension| extension.to_string_lossy().to_lowercase())
+                .map_or(false, |extension| {
+                    FRAME_EXTENSIONS.contains(&extension.as_str())
+                })
+        })
+        .collect();
+    paths.sort_by(|a, b| natord::compare_ignore_case(&a.to_string_lossy(), &b.to_string_lossy()));
+    if paths.is_empty() {
+        return Err(format!("no frames in {}", input.display()));
+    }
+    Ok(paths)
+}
+
+fn run(args: Args) -> Result<(), String> {
+    let mut palette = None;
+    let targets = args
+        .colors
+        .iter()
+        .map(|color| parse_target(color, &mut palette, &args.palette))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Masks do not depend on the target color, so every frame is masked once
+    let mut frames: Vec<(String, Mat, Mat)> = Vec::new();
+    for path in frame_paths(&args.input)? {
+        let frame = imgcodecs::imread(&path.to_string_lossy(), imgcodecs::IMREAD_COLOR)
+            .map_err(|e| format!("cannot read {}: {}", path.display(), e))?;
+        if frame.empty() {
+            return Err(format!("cannot decode {}", path.display()));
+        }
+        let mask = desired_areas_with_profile(&frame, args.mask_profile)
+            .map_err(|e| format!("cannot mask {}: {}", path.display(), e))?;
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        frames.push((name, frame, mask));
+    }
+
+    for target in targets {
+        let output_dir = args.output.join(&target.label);
+        std::fs::create_dir_all(&output_dir)
+            .map_err(|e| format!("cannot create {}: {}", output_dir.display(), e))?;
+        let finish = Finish::Solid(target.rgb);
+
+        for (frame_index, (name, frame, mask)) in frames.iter().enumerate() {
+            let mut recolored = frame.try_clone().map_err(|e| e.to_string())?;
+            recolor_frame(&mut recolored, mask, &finish, None, &[])
+                .map_err(|e| format!("cannot recolor {}: {}", name, e))?;
+            let accuracy =
+                score_frame(frame_index, &recolored, mask, &finish).map_err(|e| e.to_string())?;
+
+            let image_data = encoding::from_bgr(&recolored)
+                .map_err(|e| e.to_string())
+                .and_then(|image| {
+                    encoding::encode(&image, args.format, args.quality).map_err(|e| e.to_string())
+                })?;
+            let output_path = output_dir.join(format!("{}.{}", name, args.format.extension()));
+            std::fs::write(&output_path, image_data)
+                .map_err(|e| format!("cannot write {}: {}", output_path.display(), e))?;
+
+            match accuracy {
+                Some(accuracy) => println!(
+                    "{} (mean CIEDE2000 {:.1}{})",
+                    output_path.display(),
+                    accuracy.mean,
+                    if accuracy.poor { ", poor match" } else { "" }
+                ),
+                None => println!("{} (no paint area found)", output_path.display()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("carcaro-recolor: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! Reconciles render storage with the `image` table: deletes render objects no
+//! row refers to, rows whose objects are gone and anonymous renders past their
+//! retention. Reads the same environment as the server and prints a JSON report.
+//!
+//!     carcaro-reconcile --dry-run --anonymous-retention-days 30
+
+use carcaro::config::Config;
+use carcaro::db;
+use carcaro::functionality::reconcile::reconcile;
+use carcaro::storage::Storage;
+use carcaro::types::reconcile::ReconcileOptions;
+use clap::Parser;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    name = "carcaro-reconcile",
+    about = "Clean up orphaned renders and images with missing objects"
+)]
+struct Args {
+    /// Only report what would be deleted
+    #[arg(long)]
+    dry_run: bool,
+    /// Delete renders without a user after this many days; kept forever if not given
+    #[arg(long, value_parser = clap::value_parser!(i32).range(1..))]
+    anonymous_retention_days: Option<i32>,
+    /// Leave objects without an image row alone until they are this many hours old
+    #[arg(long, default_value_t = 24, value_parser = clap::value_parser!(i64).range(1..))]
+    orphan_grace_hours: i64,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    let config = Config::from_env();
+    let db = db::Connection::new(&config.database_url).await;
+    let storage = Storage::new(&config.storage);
+    let options = ReconcileOptions {
+        dry_run: args.dry_run,
+        anonymous_retention_days: args.anonymous_retention_days,
+        orphan_grace_hours: args.orphan_grace_hours,
+    };
+
+    let report = match reconcile(&db, &storage, &options).await {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("carcaro-reconcile: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match serde_json::to_string_pretty(&report) {
+        Ok(json) => println!("{}", json),
+        Err(e) => eprintln!("carcaro-reconcile: {}", e),
+    }
+    if report.errors.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
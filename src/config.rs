@@ -0,0 +1,184 @@
+use crate::functionality::container_generation::{DECAL_CONTAINER, TEXTURE_CONTAINER};
+
+/// Server settings, read from the environment. Every setting but the S3 keys
+/// has a default that matches a local development setup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub database_url: String,
+    pub storage: StorageConfig,
+    pub frame_cache: FrameCacheConfig,
+    pub fetch: FetchConfig,
+}
+
+/// Limits for downloading source images: base frames, textures and decals.
+#[derive(Debug, Clone)]
+pub struct FetchConfig {
+    /// Hosts images may be fetched from. An entry starting with a dot also
+    /// allows every subdomain, like `.blob.core.windows.net`.
+    pub allowed_hosts: Vec<String>,
+    pub connect_timeout_secs: u64,
+    /// Longest wait for the next part of a response body.
+    pub read_timeout_secs: u64,
+    /// Longest a whole download may take.
+    pub total_timeout_secs: u64,
+    pub max_bytes: u64,
+    /// Largest width times height an image may decode to.
+    pub max_pixels: u64,
+}
+
+/// On-disk cache of downloaded base frames.
+#[derive(Debug, Clone)]
+pub struct FrameCacheConfig {
+    pub dir: String,
+    /// Cached bytes above which the least recently used frames are evicted.
+    pub max_bytes: u64,
+    /// How long a cached frame is used without asking the origin whether it changed.
+    pub fresh_for_secs: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    Azure,
+    S3,
+}
+
+impl StorageBackend {
+    pub fn from_name(name: &str) -> Option<StorageBackend> {
+        match name {
+            "azure" => Some(StorageBackend::Azure),
+            "s3" => Some(StorageBackend::S3),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StorageConfig {
+    pub backend: StorageBackend,
+    pub account: String,
+    /// File holding the account access key.
+    pub access_key_file: String,
+    /// Container all renders are stored in; a bucket on S3.
+    pub container: String,
+    /// Endpoint to use instead of the public cloud one. For Azure it includes
+    /// the account, like `http://127.0.0.1:10000/devstoreaccount1`; for S3 it
+    /// is the server, like `http://127.0.0.1:9000`.
+    pub endpoint: Option<String>,
+    /// Talk to a local Azurite with its well-known development account; no
+    /// key file is needed.
+    pub emulator: bool,
+    pub s3: S3Config,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct S3Config {
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+fn env_or(name: &str, default: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| default.to_string())
+}
+
+fn env_required(name: &str) -> String {
+    std::env::var(name).unwrap_or_else(|_| panic!("{} must be set", name))
+}
+
+fn env_number(name: &str, default: u64) -> u64 {
+    match std::env::var(name) {
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number, not {}", name, value)),
+        Err(_) => default,
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let storage = StorageConfig::from_env();
+        Config {
+            database_url: env_or(
+                "CARCARO_DATABASE_URL",
+                "postgres://postgres:a@localhost:5432/carcaro",
+            ),
+            fetch: FetchConfig {
+                allowed_hosts: match std::env::var("CARCARO_FETCH_ALLOWED_HOSTS") {
+                    Ok(hosts) => hosts
+                        .split(',')
+                        .map(|host| host.trim().to_lowercase())
+                        .filter(|host| !host.is_empty())
+                        .collect(),
+                    Err(_) => storage.hosts(),
+                },
+                connect_timeout_secs: env_number("CARCARO_FETCH_CONNECT_TIMEOUT_SECS", 5),
+                read_timeout_secs: env_number("CARCARO_FETCH_READ_TIMEOUT_SECS", 15),
+                total_timeout_secs: env_number("CARCARO_FETCH_TOTAL_TIMEOUT_SECS", 60),
+                max_bytes: env_number("CARCARO_FETCH_MAX_BYTES", 32 * 1024 * 1024),
+                max_pixels: env_number("CARCARO_FETCH_MAX_PIXELS", 40_000_000),
+            },
+            storage,
+            frame_cache: FrameCacheConfig {
+                dir: env_or("CARCARO_FRAME_CACHE_DIR", "cache/frames"),
+                max_bytes: env_number("CARCARO_FRAME_CACHE_MAX_BYTES", 2 * 1024 * 1024 * 1024),
+                fresh_for_secs: env_number("CARCARO_FRAME_CACHE_FRESH_SECS", 24 * 60 * 60),
+            },
+        }
+    }
+}
+
+impl StorageConfig {
+    /// Hosts objects in this storage are served from.
+    pub fn hosts(&self) -> Vec<String> {
+        if let Some(endpoint) = &self.endpoint {
+            return reqwest::Url::parse(endpoint)
+                .ok()
+                .and_then(|url| url.host_str().map(|host| host.to_lowercase()))
+                .into_iter()
+                .collect();
+        }
+        match self.backend {
+            StorageBackend::Azure if self.emulator => vec!["127.0.0.1".to_string()],
+            StorageBackend::Azure => vec![format!("{}.blob.core.windows.net", self.account)],
+            // Buckets are addressed as `{bucket}.{region host}`, so only the
+            // buckets frames, textures and decals are read from are allowed
+            StorageBackend::S3 => match self.s3.region.parse::<s3::Region>() {
+                Ok(region) => [self.container.as_str(), TEXTURE_CONTAINER, DECAL_CONTAINER]
+                    .iter()
+                    .map(|bucket| format!("{}.{}", bucket, region.host()).to_lowercase())
+                    .collect(),
+                Err(_) => Vec::new(),
+            },
+        }
+    }
+
+    pub fn from_env() -> StorageConfig {
+        let backend = env_or("CARCARO_STORAGE_BACKEND", "azure");
+        let backend = StorageBackend::from_name(&backend)
+            .unwrap_or_else(|| panic!("Unknown storage backend {}", backend));
+        StorageConfig {
+            s3: match backend {
+                StorageBackend::S3 => S3Config::from_env(),
+                StorageBackend::Azure => S3Config::default(),
+            },
+            backend,
+            account: env_or("CARCARO_STORAGE_ACCOUNT", "wrapmycar"),
+            access_key_file: env_or("CARCARO_STORAGE_KEY_FILE", "src/key.txt"),
+            container: env_or("CARCARO_STORAGE_CONTAINER", "renders"),
+            endpoint: std::env::var("CARCARO_STORAGE_ENDPOINT").ok(),
+            emulator: env_or("CARCARO_STORAGE_EMULATOR", "false") == "true",
+        }
+    }
+}
+
+impl S3Config {
+    /// The keys have no defaults, so a server is never opened with the
+    /// well-known credentials of a development setup by mistake.
+    pub fn from_env() -> S3Config {
+        S3Config {
+            region: env_or("CARCARO_S3_REGION", "us-east-1"),
+            access_key_id: env_required("CARCARO_S3_ACCESS_KEY_ID"),
+            secret_access_key: env_required("CARCARO_S3_SECRET_ACCESS_KEY"),
+        }
+    }
+}
@@ -1,9 +1,15 @@
+use crate::types::accuracy::FrameAccuracy;
+use crate::types::animation::{Animation, AnimationFormat, NewAnimation};
 use crate::types::car::{Car, CarId};
 use crate::types::color::Color;
+use crate::types::color_wall::{ColorWall, ColorWallEntry, ColorWallStatus};
+use crate::types::decal::DecalPlacement;
 use crate::types::image::{Image, ImageId, NewImage};
 use crate::types::image_request::ImageRequest;
 use crate::types::user::{NewUser, User, UserCredentials, UserId};
+use crate::types::output::{FrameVariants, SpriteSheet};
 use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
+use sqlx::types::Json;
 use sqlx::{query, Error, Row};
 use crate::types::favorite::Favorite;
 
@@ -23,6 +29,10 @@ impl Connection {
             Err(e) => panic!("Couldn't establish DB connection {}", e),
         };
 
+        if let Err(e) = sqlx::migrate!().run(&db_pool).await {
+            panic!("Couldn't run DB migrations {}", e);
+        }
+
         Connection {
             connection: db_pool,
         }
@@ -69,7 +79,8 @@ impl Connection {
     ) -> Result<Image, Error> {
         let query = query(
             r#"
-            SELECT image.imageid, image.url, image.colors
+            SELECT image.imageid, image.url, image.colors, image.frame_count, image.variants,
+                image.sprite_sheet, image.base_imageid, image.accuracy
             FROM image
             INNER JOIN car ON image.imageid = car.imageid
             WHERE car.make = $1 AND car.model = $2 AND car.year = $3
@@ -91,27 +102,66 @@ impl Connection {
             url: res.get("url"),
             colors: res.get("colors"),
             userid: None,
+            frame_count: res.get("frame_count"),
+            base_imageid: res.get("base_imageid"),
+            variants: res.get::<Json<Vec<FrameVariants>>, _>("variants").0,
+            sprite_sheet: res
+                .get::<Option<Json<SpriteSheet>>, _>("sprite_sheet")
+                .map(|sprite_sheet| sprite_sheet.0),
+            accuracy: res.get::<Json<Vec<FrameAccuracy>>, _>("accuracy").0,
         };
 
         Ok(images)
     }
 
-    pub async fn add_new_image(&self, new_image: NewImage) -> Result<Image, Error> {
+    /// Takes the next image id before the render is stored, so its storage
+    /// keys can contain the id.
+    pub async fn reserve_image_id(&self) -> Result<ImageId, Error> {
+        let query = sqlx::query(
+            "SELECT nextval(pg_get_serial_sequence('image', 'imageid'))::integer AS imageid",
+        )
+        .map(|row: PgRow| ImageId(row.get("imageid")));
+
+        match query.fetch_one(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Database error {:?}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    pub async fn add_new_image(&self, id: ImageId, new_image: NewImage) -> Result<Image, Error> {
         let query = sqlx::query(
             r#"
-            INSERT INTO image (url, colors, userid)
-            VALUES($1, $2, $3)
-            RETURNING imageid, url, colors, userid
+            INSERT INTO image (imageid, url, colors, userid, frame_count, variants, sprite_sheet,
+                base_imageid, accuracy)
+            VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING imageid, url, colors, userid, frame_count, variants, sprite_sheet, base_imageid,
+                accuracy
         "#,
         )
+        .bind(id.0)
         .bind(new_image.url)
         .bind(new_image.colors)
         .bind(new_image.userid)
+        .bind(new_image.frame_count)
+        .bind(Json(new_image.variants))
+        .bind(new_image.sprite_sheet.map(Json))
+        .bind(new_image.base_imageid)
+        .bind(Json(new_image.accuracy))
         .map(|row| Image {
             id: ImageId(row.get("imageid")),
             url: row.get("url"),
             colors: row.get("colors"),
             userid: row.get("userid"),
+            frame_count: row.get("frame_count"),
+            base_imageid: row.get("base_imageid"),
+            variants: row.get::<Json<Vec<FrameVariants>>, _>("variants").0,
+            sprite_sheet: row
+                .get::<Option<Json<SpriteSheet>>, _>("sprite_sheet")
+                .map(|sprite_sheet| sprite_sheet.0),
+            accuracy: row.get::<Json<Vec<FrameAccuracy>>, _>("accuracy").0,
         });
 
         match query.fetch_one(&self.connection).await {
@@ -126,7 +176,7 @@ impl Connection {
     pub async fn extract_image(&self, imageid: i32) -> Result<ImageRequest, Error> {
         let query = sqlx::query(
             r#"
-                SELECT image.url
+                SELECT image.url, image.frame_count
                 FROM image
                 WHERE image.imageid = $1
             "#,
@@ -135,6 +185,7 @@ impl Connection {
         .map(|row: PgRow| ImageRequest {
             url: row.get("url"),
             colors: [0, 0, 0],
+            frame_count: row.get("frame_count"),
         });
 
         match query.fetch_one(&self.connection).await {
@@ -176,6 +227,30 @@ impl Connection {
         Ok(colors)
     }
 
+    pub async fn get_color_by_hex(&self, hex: String) -> Result<Color, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT color.ral, color.name, color.hex
+            FROM color
+            WHERE upper(trim(leading '#' from color.hex)) = upper($1)
+            "#,
+        )
+        .bind(hex)
+        .map(|row: PgRow| Color {
+            ral: row.get("ral"),
+            color_name: row.get("name"),
+            hex: row.get("hex"),
+        });
+
+        match query.fetch_one(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Error {}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
     pub async fn get_user_favorites(&self, userid: UserId) -> Result<Vec<Favorite>, Error> {
         let query = sqlx::query(
             r#"
@@ -261,4 +336,366 @@ impl Connection {
             }
         }
     }
+
+    pub async fn get_decal_placements(&self, imageid: i32) -> Result<Vec<DecalPlacement>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT frame, url, x, y, scale, rotation
+            FROM decal_placement
+            WHERE imageid = $1
+            ORDER BY frame, placementid
+            "#,
+        )
+        .bind(imageid)
+        .map(|row: PgRow| DecalPlacement {
+            frame: row.get::<i32, _>("frame") as usize,
+            url: row.get("url"),
+            x: row.get("x"),
+            y: row.get("y"),
+            scale: row.get("scale"),
+            rotation: row.get("rotation"),
+        });
+
+        match query.fetch_all(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Error executing query: {:?}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    pub async fn save_decal_placements(
+        &self,
+        imageid: i32,
+        decals: Vec<DecalPlacement>,
+    ) -> Result<Vec<DecalPlacement>, Error> {
+        let mut tx = self.connection.begin().await?;
+
+        sqlx::query("DELETE FROM decal_placement WHERE imageid = $1")
+            .bind(imageid)
+            .execute(&mut *tx)
+            .await?;
+
+        for decal in &decals {
+            sqlx::query(
+                r#"
+                INSERT INTO decal_placement (imageid, frame, url, x, y, scale, rotation)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+            )
+            .bind(imageid)
+            .bind(decal.frame as i32)
+            .bind(&decal.url)
+            .bind(decal.x)
+            .bind(decal.y)
+            .bind(decal.scale)
+            .bind(decal.rotation)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(decals)
+    }
+
+    pub async fn get_image(&self, imageid: i32) -> Result<Image, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT imageid, url, colors, userid, frame_count, variants, sprite_sheet, base_imageid,
+                accuracy
+            FROM image
+            WHERE imageid = $1
+            "#,
+        )
+        .bind(imageid)
+        .map(image_from_row);
+
+        match query.fetch_one(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Error {}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    pub async fn get_images(&self) -> Result<Vec<Image>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT imageid, url, colors, userid, frame_count, variants, sprite_sheet, base_imageid,
+                accuracy
+            FROM image
+            ORDER BY imageid
+            "#,
+        )
+        .map(image_from_row);
+
+        match query.fetch_all(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Error {}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    /// Images without a user that were created more than `retention_days` ago.
+    /// Base sets have no user either, so callers have to tell them apart.
+    pub async fn get_expired_anonymous_images(
+        &self,
+        retention_days: i32,
+    ) -> Result<Vec<ImageId>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT imageid
+            FROM image
+            WHERE userid IS NULL AND created_at < now() - make_interval(days => $1)
+            "#,
+        )
+        .bind(retention_days)
+        .map(|row: PgRow| ImageId(row.get("imageid")));
+
+        match query.fetch_all(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Error {}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    /// Deletes image rows together with their animations and decal placements,
+    /// and takes them out of the indexes of the color walls they were part of.
+    pub async fn delete_images(&self, imageids: &[ImageId]) -> Result<u64, Error> {
+        let imageids: Vec<i32> = imageids.iter().map(|id| id.0).collect();
+        let mut transaction = self.connection.begin().await?;
+        sqlx::query(
+            r#"
+            UPDATE color_wall
+            SET renders = (
+                SELECT coalesce(jsonb_agg(entry ORDER BY entry_position), '[]'::jsonb)
+                FROM jsonb_array_elements(renders) WITH ORDINALITY AS e (entry, entry_position)
+                WHERE NOT coalesce((entry ->> 'imageid')::integer = ANY($1), false)
+            )
+            WHERE EXISTS (
+                SELECT 1
+                FROM jsonb_array_elements(renders) AS e (entry)
+                WHERE (entry ->> 'imageid')::integer = ANY($1)
+            )
+            "#,
+        )
+        .bind(imageids.clone())
+        .execute(&mut *transaction)
+        .await?;
+        let result = sqlx::query("DELETE FROM image WHERE imageid = ANY($1)")
+            .bind(imageids)
+            .execute(&mut *transaction)
+            .await?;
+        transaction.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Animations of any of the images.
+    pub async fn get_animations_of_images(
+        &self,
+        imageids: &[ImageId],
+    ) -> Result<Vec<Animation>, Error> {
+        let imageids: Vec<i32> = imageids.iter().map(|id| id.0).collect();
+        let query = sqlx::query(
+            r#"
+            SELECT animationid, imageid, format, url, width, height, delay_ms
+            FROM animation
+            WHERE imageid = ANY($1)
+            ORDER BY animationid
+            "#,
+        )
+        .bind(imageids)
+        .map(animation_from_row);
+
+        match query.fetch_all(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Error executing query: {:?}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    pub async fn add_animation(&self, new_animation: NewAnimation) -> Result<Animation, Error> {
+        let query = sqlx::query(
+            r#"
+            INSERT INTO animation (imageid, format, url, width, height, delay_ms)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING animationid, imageid, format, url, width, height, delay_ms
+            "#,
+        )
+        .bind(new_animation.imageid.0)
+        .bind(new_animation.format.name())
+        .bind(new_animation.url)
+        .bind(new_animation.width as i32)
+        .bind(new_animation.height as i32)
+        .bind(new_animation.delay_ms as i32)
+        .map(animation_from_row);
+
+        match query.fetch_one(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Database error {:?}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    pub async fn get_animations(&self, imageid: i32) -> Result<Vec<Animation>, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT animationid, imageid, format, url, width, height, delay_ms
+            FROM animation
+            WHERE imageid = $1
+            ORDER BY animationid
+            "#,
+        )
+        .bind(imageid)
+        .map(animation_from_row);
+
+        match query.fetch_all(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Error executing query: {:?}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    pub async fn create_color_wall(
+        &self,
+        imageid: i32,
+        userid: Option<i32>,
+        total: i32,
+    ) -> Result<ColorWall, Error> {
+        let query = sqlx::query(
+            r#"
+            INSERT INTO color_wall (imageid, userid, status, total)
+            VALUES ($1, $2, $3, $4)
+            RETURNING color_wallid, imageid, status, total, completed, renders
+            "#,
+        )
+        .bind(imageid)
+        .bind(userid)
+        .bind(ColorWallStatus::Running.name())
+        .bind(total)
+        .map(color_wall_from_row);
+
+        match query.fetch_one(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Database error {:?}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+
+    /// Appends one finished color to the job's index and counts it as completed.
+    pub async fn add_color_wall_entry(
+        &self,
+        color_wallid: i32,
+        entry: ColorWallEntry,
+    ) -> Result<(), Error> {
+        sqlx::query(
+            r#"
+            UPDATE color_wall
+            SET completed = completed + 1, renders = renders || $2
+            WHERE color_wallid = $1
+            "#,
+        )
+        .bind(color_wallid)
+        .bind(Json(vec![entry]))
+        .execute(&self.connection)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_color_wall_status(
+        &self,
+        color_wallid: i32,
+        status: ColorWallStatus,
+    ) -> Result<(), Error> {
+        sqlx::query("UPDATE color_wall SET status = $2 WHERE color_wallid = $1")
+            .bind(color_wallid)
+            .bind(status.name())
+            .execute(&self.connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Marks every job that is still running as failed. Jobs run inside the
+    /// server process, so after a restart nothing is working on them anymore.
+    pub async fn fail_running_color_walls(&self) -> Result<u64, Error> {
+        let result = sqlx::query("UPDATE color_wall SET status = $2 WHERE status = $1")
+            .bind(ColorWallStatus::Running.name())
+            .bind(ColorWallStatus::Failed.name())
+            .execute(&self.connection)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn get_color_wall(&self, color_wallid: i32) -> Result<ColorWall, Error> {
+        let query = sqlx::query(
+            r#"
+            SELECT color_wallid, imageid, status, total, completed, renders
+            FROM color_wall
+            WHERE color_wallid = $1
+            "#,
+        )
+        .bind(color_wallid)
+        .map(color_wall_from_row);
+
+        match query.fetch_one(&self.connection).await {
+            Ok(res) => Ok(res),
+            Err(e) => {
+                eprintln!("Error {}", e);
+                Err(Error::RowNotFound)
+            }
+        }
+    }
+}
+
+fn image_from_row(row: PgRow) -> Image {
+    Image {
+        id: ImageId(row.get("imageid")),
+        url: row.get("url"),
+        colors: row.get("colors"),
+        userid: row.get("userid"),
+        frame_count: row.get("frame_count"),
+        base_imageid: row.get("base_imageid"),
+        variants: row.get::<Json<Vec<FrameVariants>>, _>("variants").0,
+        sprite_sheet: row
+            .get::<Option<Json<SpriteSheet>>, _>("sprite_sheet")
+            .map(|sprite_sheet| sprite_sheet.0),
+        accuracy: row.get::<Json<Vec<FrameAccuracy>>, _>("accuracy").0,
+    }
+}
+
+fn color_wall_from_row(row: PgRow) -> ColorWall {
+    ColorWall {
+        id: row.get("color_wallid"),
+        imageid: ImageId(row.get("imageid")),
+        status: ColorWallStatus::from_name(row.get("status")).unwrap_or(ColorWallStatus::Failed),
+        total: row.get("total"),
+        completed: row.get("completed"),
+        renders: row.get::<Json<Vec<ColorWallEntry>>, _>("renders").0,
+    }
+}
+
+fn animation_from_row(row: PgRow) -> Animation {
+    Animation {
+        id: row.get("animationid"),
+        imageid: ImageId(row.get("imageid")),
+        format: AnimationFormat::from_name(row.get("format")).unwrap_or_default(),
+        url: row.get("url"),
+        width: row.get::<i32, _>("width") as u32,
+        height: row.get::<i32, _>("height") as u32,
+        delay_ms: row.get::<i32, _>("delay_ms") as u32,
+    }
 }
@@ -0,0 +1,186 @@
+use crate::functionality::recolor::continuous_data;
+use crate::types::accuracy::{FrameAccuracy, POOR_MEAN_DELTA_E};
+use crate::types::finish::Finish;
+use opencv::core::{Mat, MatTraitConst};
+use opencv::imgproc;
+use palette::color_difference::Ciede2000;
+use palette::{IntoColor, Lab, Srgb};
+use rayon::prelude::*;
+
+/// Only every n-th row and column is scored, which is plenty for statistics
+/// and keeps the Lab conversions off the critical path of a render.
+const SAMPLE_STEP: usize = 2;
+
+/// Feathered mask edges are mostly the original frame, so only pixels that
+/// are at least this opaque in the mask count.
+const MIN_MASK_VALUE: u8 = 128;
+
+pub(crate) fn to_lab(rgb: [u8; 3]) -> Lab {
+    Srgb::new(rgb[0], rgb[1], rgb[2])
+        .into_format::<f32>()
+        .into_color()
+}
+
+/// Scores a recolored BGR frame against the finish it was painted with.
+/// Textures have no single target color per pixel, so they are not scored.
+pub fn score_frame(
+    frame: usize,
+    recolored: &Mat,
+    mask: &Mat,
+    finish: &Finish,
+) -> Result<Option<FrameAccuracy>, opencv::Error> {
+    let deltas = match finish {
+        Finish::Solid(target_color) => {
+            let target = to_lab(*target_color);
+            delta_e_samples(recolored, mask, |_, _| target)?
+        }
+        Finish::Gradient(gradient) => {
+            // Same placement of the gradient as `recolor_frame`
+            let bounds = imgproc::bounding_rect(mask)?;
+            let lut: Vec<Lab> = (0..=255)
+                .map(|i| to_lab(gradient.color_at(i as f64 / 255.0)))
+                .collect();
+            delta_e_samples(recolored, mask, |x, y| {
+                let t = gradient.position(x - bounds.x, y - bounds.y, bounds.width, bounds.height);
+                lut[(t * 255.0).round() as usize]
+            })?
+        }
+        Finish::Texture(_) => return Ok(None),
+    };
+    Ok(summarize(frame, deltas))
+}
+
+fn delta_e_samples<F>(recolored: &Mat, mask: &Mat, target_at: F) -> Result<Vec<f32>, opencv::Error>
+where
+    F: Fn(i32, i32) -> Lab + Sync,
+{
+    let width = recolored.cols() as usize;
+    if width == 0 {
+        return Ok(Vec::new());
+    }
+    let (mut image_copy, mut mask_copy) = (Mat::default(), Mat::default());
+    let image_data = continuous_data(recolored, &mut image_copy)?;
+    let mask_data = continuous_data(mask, &mut mask_copy)?;
+
+    let deltas = image_data
+        .par_chunks(width * 3)
+        .zip(mask_data.par_chunks(width))
+        .enumerate()
+        .filter(|(y, _)| y % SAMPLE_STEP == 0)
+        .flat_map_iter(|(y, (row, mask_row))| {
+            let target_at = &target_at;
+            row.chunks_exact(3)
+                .zip(mask_row)
+                .enumerate()
+                .step_by(SAMPLE_STEP)
+                .filter(|(_, (_, &mask_value))| mask_value >= MIN_MASK_VALUE)
+                .map(move |(x, (bgr_pixel, _))| {
+                    let pixel = to_lab([bgr_pixel[2], bgr_pixel[1], bgr_pixel[0]]);
+                    pixel.difference(target_at(x as i32, y as i32))
+                })
+        })
+        .collect();
+    Ok(deltas)
+}
+
+fn summarize(frame: usize, mut deltas: Vec<f32>) -> Option<FrameAccuracy> {
+    if deltas.is_empty() {
+        return None;
+    }
+    deltas.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |p: f64| deltas[((deltas.len() - 1) as f64 * p).round() as usize] as f64;
+    let mean = deltas.iter().map(|&delta| delta as f64).sum::<f64>() / deltas.len() as f64;
+
+    Some(FrameAccuracy {
+        frame,
+        mean,
+        median: percentile(0.5),
+        p95: percentile(0.95),
+        pixels: deltas.len(),
+        poor: mean > POOR_MEAN_DELTA_E,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{Scalar, CV_8UC1, CV_8UC3};
+
+    /// Pairs from Sharma, Wu and Dalal, "The CIEDE2000 color-difference
+    /// formula: implementation notes, supplementary test data, and
+    /// mathematical observations", with their reference ΔE.
+    const SHARMA_PAIRS: [([f32; 3], [f32; 3], f32); 8] = [
+        ([50.0, 2.6772, -79.7751], [50.0, 0.0, -82.7485], 2.0425),
+        ([50.0, 0.0, 0.0], [50.0, -1.0, 2.0], 2.3669),
+        ([50.0, 2.5, 0.0], [73.0, 25.0, -18.0], 27.1492),
+        ([50.0, 2.5, 0.0], [61.0, -5.0, 29.0], 22.8977),
+        ([50.0, 2.5, 0.0], [56.0, -27.0, -3.0], 31.9030),
+        ([50.0, 2.5, 0.0], [58.0, 24.0, 15.0], 19.4535),
+        ([50.0, 2.5, 0.0], [50.0, 3.1736, 0.5854], 1.0000),
+        (
+            [60.2574, -34.0099, 36.2677],
+            [60.4626, -34.1751, 39.4387],
+            1.2644,
+        ),
+    ];
+
+    fn lab(lab: [f32; 3]) -> Lab {
+        Lab::new(lab[0], lab[1], lab[2])
+    }
+
+    fn sharma_deltas() -> Vec<f32> {
+        SHARMA_PAIRS
+            .iter()
+            .map(|&(a, b, _)| lab(a).difference(lab(b)))
+            .collect()
+    }
+
+    #[test]
+    fn matches_the_sharma_reference_pairs() {
+        for (delta, (_, _, expected)) in sharma_deltas().iter().zip(SHARMA_PAIRS) {
+            assert!((delta - expected).abs() < 1e-3, "{} != {}", delta, expected);
+        }
+    }
+
+    #[test]
+    fn summarizes_mean_median_and_p95() {
+        let accuracy = summarize(3, sharma_deltas()).unwrap();
+        // Sorted: 1.0000 1.2644 2.0425 2.3669 19.4535 22.8977 27.1492 31.9030
+        assert_eq!(accuracy.frame, 3);
+        assert_eq!(accuracy.pixels, 8);
+        assert!((accuracy.mean - 13.50965).abs() < 1e-3, "{}", accuracy.mean);
+        assert!(
+            (accuracy.median - 19.4535).abs() < 1e-3,
+            "{}",
+            accuracy.median
+        );
+        assert!((accuracy.p95 - 31.9030).abs() < 1e-3, "{}", accuracy.p95);
+        assert!(accuracy.poor);
+    }
+
+    #[test]
+    fn summarizes_nothing_for_no_samples() {
+        assert_eq!(summarize(0, Vec::new()), None);
+    }
+
+    #[test]
+    fn scores_nothing_for_an_empty_mask() {
+        let frame = Mat::new_rows_cols_with_default(10, 10, CV_8UC3, Scalar::all(90.0)).unwrap();
+        let mask = Mat::new_rows_cols_with_default(10, 10, CV_8UC1, Scalar::all(0.0)).unwrap();
+        let finish = Finish::Solid([90, 90, 90]);
+        assert_eq!(score_frame(0, &frame, &mask, &finish).unwrap(), None);
+    }
+
+    #[test]
+    fn scores_an_exact_match_as_zero() {
+        let bgr = Scalar::new(30.0, 60.0, 200.0, 0.0);
+        let frame = Mat::new_rows_cols_with_default(10, 10, CV_8UC3, bgr).unwrap();
+        let mask = Mat::new_rows_cols_with_default(10, 10, CV_8UC1, Scalar::all(255.0)).unwrap();
+        let finish = Finish::Solid([200, 60, 30]);
+        let accuracy = score_frame(0, &frame, &mask, &finish).unwrap().unwrap();
+        // Every second row and column
+        assert_eq!(accuracy.pixels, 25);
+        assert!(accuracy.mean < 1e-3 && accuracy.p95 < 1e-3);
+        assert!(!accuracy.poor);
+    }
+}
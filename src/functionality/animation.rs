@@ -0,0 +1,112 @@
+use crate::functionality::color_swap::download_image;
+use crate::handle_errors::Error;
+use crate::types::animation::{AnimationFormat, AnimationRequest};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::imageops::FilterType;
+use image::{Delay, DynamicImage, Frame, RgbaImage};
+
+const GIF_SPEED: i32 = 10;
+
+pub struct EncodedAnimation {
+    pub data: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Downloads the frames of a render and assembles them into one looping animation.
+pub async fn export_animation(
+    frame_urls: Vec<String>,
+    request: &AnimationRequest,
+) -> Result<EncodedAnimation, Error> {
+    let mut downloaded = Vec::new();
+    for url in frame_urls {
+        downloaded.push(download_image(url).await?);
+    }
+    // Resizing and encoding are CPU-bound, so they stay off the async workers
+    let request = request.clone();
+    tokio::task::spawn_blocking(move || assemble_animation(downloaded, &request))
+        .await
+        .map_err(|_| Error::AnimationError)?
+}
+
+fn assemble_animation(
+    downloaded: Vec<DynamicImage>,
+    request: &AnimationRequest,
+) -> Result<EncodedAnimation, Error> {
+    let mut frames: Vec<RgbaImage> = Vec::new();
+    for frame in downloaded {
+        let width = request.width.min(frame.width());
+        let height = ((frame.height() as f64 * width as f64 / frame.width() as f64).round() as u32).max(1);
+        frames.push(frame.resize_exact(width, height, FilterType::Triangle).to_rgba8());
+    }
+    let (width, height) = match frames.first() {
+        Some(first) => first.dimensions(),
+        None => return Err(Error::InvalidAnimation),
+    };
+    if frames.iter().any(|frame| frame.dimensions() != (width, height)) {
+        return Err(Error::FrameSizeMismatch);
+    }
+
+    let data = match request.format {
+        AnimationFormat::Gif => encode_gif(frames, request.delay_ms),
+        AnimationFormat::Apng => encode_apng(frames, width, height, request.delay_ms),
+        AnimationFormat::Webp => encode_webp(frames, width, height, request.delay_ms),
+    }?;
+    Ok(EncodedAnimation { data, width, height })
+}
+
+fn encode_gif(frames: Vec<RgbaImage>, delay_ms: u32) -> Result<Vec<u8>, Error> {
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = GifEncoder::new_with_speed(&mut buffer, GIF_SPEED);
+        encoder
+            .set_repeat(Repeat::Infinite)
+            .map_err(|_| Error::AnimationError)?;
+        let delay = Delay::from_numer_denom_ms(delay_ms, 1);
+        encoder
+            .encode_frames(frames.into_iter().map(|frame| Frame::from_parts(frame, 0, 0, delay)))
+            .map_err(|_| Error::AnimationError)?;
+    }
+    Ok(buffer)
+}
+
+/// The `image` crate only writes still PNGs, so animated PNGs go through the
+/// `png` crate it is built on.
+fn encode_apng(frames: Vec<RgbaImage>, width: u32, height: u32, delay_ms: u32) -> Result<Vec<u8>, Error> {
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut buffer, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .set_animated(frames.len() as u32, 0)
+            .map_err(|_| Error::AnimationError)?;
+        encoder
+            .set_frame_delay(delay_ms.min(u16::MAX as u32) as u16, 1000)
+            .map_err(|_| Error::AnimationError)?;
+
+        let mut writer = encoder.write_header().map_err(|_| Error::AnimationError)?;
+        for frame in &frames {
+            writer
+                .write_image_data(frame.as_raw())
+                .map_err(|_| Error::AnimationError)?;
+        }
+        writer.finish().map_err(|_| Error::AnimationError)?;
+    }
+    Ok(buffer)
+}
+
+/// The `image` crate cannot write animated WebP either, so this uses libwebp's muxer.
+fn encode_webp(frames: Vec<RgbaImage>, width: u32, height: u32, delay_ms: u32) -> Result<Vec<u8>, Error> {
+    let mut encoder =
+        webp_animation::Encoder::new((width, height)).map_err(|_| Error::AnimationError)?;
+    let mut timestamp = 0;
+    for frame in &frames {
+        encoder
+            .add_frame(frame.as_raw(), timestamp)
+            .map_err(|_| Error::AnimationError)?;
+        timestamp += delay_ms as i32;
+    }
+    let webp = encoder.finalize(timestamp).map_err(|_| Error::AnimationError)?;
+    Ok(webp.to_vec())
+}
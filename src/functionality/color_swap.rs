@@ -1,117 +1,221 @@
 use crate::handle_errors::Error;
+use crate::functionality::accuracy::score_frame;
+use crate::functionality::fetch::fetcher;
+use crate::functionality::encoding;
+use crate::functionality::frame_cache;
+use crate::functionality::mask::{refine_mask, MaskProfile};
+use crate::functionality::recolor::{
+    apply_color_change, apply_decal, apply_texture, decal_to_bgra, PaintTarget,
+};
+use crate::types::accuracy::FrameAccuracy;
+use crate::types::decal::DecalPlacement;
+use crate::types::finish::Finish;
+use crate::types::frame::BaseFrame;
 use colorsys::{Hsl, Rgb};
-use image::{Rgba};
+use futures::future::try_join_all;
+use image::{DynamicImage, Rgba};
 use opencv::{imgcodecs, imgproc};
-use opencv::core::{CV_8U, Mat, MatTrait, MatTraitConst, Scalar, Vec3b};
-
-pub async fn color_swap(base_urls: Vec<String>, target_color: [u8; 3]) -> Result<(), Error> {
-    println!("Started mask and models extraction");
-    extract_mask_and_model(base_urls, "base")
-        .await
-        .expect("Failed to extract model");
-    println!("Extracted mask and models");
-    apply_color_shift(target_color)
-        .await
-        .expect("Failed to apply hue shift");
-    println!("applied hue shift");
-    Ok(())
-}
+use opencv::core::{Mat, MatTraitConst, CV_8UC4};
+
+pub async fn color_swap(
+    base_frames: Vec<BaseFrame>,
+    finish: Finish,
+    decals: Vec<DecalPlacement>,
+) -> Result<(Vec<DynamicImage>, Vec<FrameAccuracy>), Error> {
+    let frame_count = base_frames.len();
+    if decals.iter().any(|decal| decal.frame >= frame_count) {
+        return Err(Error::InvalidDecal);
+    }
 
-async fn extract_mask_and_model(urls: Vec<String>, dir: &str) -> Result<(), Error> {
-    let mut tasks = Vec::new();
-    let dir_arc = std::sync::Arc::new(dir.to_string());
+    // Everything stays in memory, so renders running at the same time never
+    // see each other's frames
+    let frames = try_join_all(
+        base_frames
+            .iter()
+            .map(|frame| download_base_frame(frame, imgcodecs::IMREAD_COLOR)),
+    )
+    .await?;
+    println!("Downloaded base frames");
+    let texture_image = match &finish {
+        Finish::Texture(texture) => {
+            Some(download_frame(texture.url.clone(), imgcodecs::IMREAD_COLOR).await?)
+        }
+        _ => None,
+    };
 
-    for (i, u) in urls.iter().enumerate() {
-        let task = tokio::spawn(prepare_images(i, u.clone(), dir_arc.clone()));
-        tasks.push(task)
-    }
-    for task in tasks {
-        task.await.unwrap().expect("TODO: panic message");
+    // Each distinct decal image is downloaded once and shared by all frames using it
+    let mut decal_images: Vec<(String, Mat)> = Vec::new();
+    for decal in &decals {
+        if !decal_images.iter().any(|(url, _)| *url == decal.url) {
+            let decal_image = download_decal(decal.url.clone()).await?;
+            decal_images.push((decal.url.clone(), decal_image));
+        }
     }
-    Ok(())
-}
-
-async fn prepare_images(i: usize, url: String, dir: std::sync::Arc<String>) -> Result<(), Error> {
-    let response = reqwest::get(url).await.unwrap();
-    if !response.status().is_success() {
-        return Err(Error::ColorSwapError);
+    if !decal_images.is_empty() {
+        println!("Downloaded decals");
     }
-    let img_bytes = response.bytes().await.unwrap();
-
-    let image = image::load_from_memory(&img_bytes).unwrap();
-    let filename = format!("src/{}/saved_{}.png", dir, i);
-    image.save(filename).expect("failed to save");
-    Ok(())
-}
 
-pub async fn apply_color_shift(target_color: [u8; 3]) -> Result<(), Error> {
     let mut tasks = Vec::new();
-    for i in 0..=11 {
-        let base_i = format!("src/base/saved_{}.png", i);
-        let base_o = format!("src/mask/saved_{}.png", i);
-
-        let task = tokio::spawn(async move {
-            //let mut base_image = image::open(&base_io).expect("Failed to open image");
-            colorize_images(&base_i, &base_o, target_color)
-                .await
-                .expect("Failed to colorize images");
-            println!("colorized images");
-        });
-        tasks.push(task);
+    for (i, frame) in frames.into_iter().enumerate() {
+        let finish = finish.clone();
+        let texture_image = texture_image
+            .as_ref()
+            .map(|texture_image| texture_image.try_clone())
+            .transpose()
+            .map_err(|_| Error::ColorSwapError)?;
+        let mut frame_decals = Vec::new();
+        for decal in decals.iter().filter(|decal| decal.frame == i) {
+            let (_, decal_image) = decal_images
+                .iter()
+                .find(|(url, _)| *url == decal.url)
+                .ok_or(Error::InvalidDecal)?;
+            let decal_image = decal_image.try_clone().map_err(|_| Error::ColorSwapError)?;
+            frame_decals.push((decal.clone(), decal_image));
+        }
+        // Recoloring is CPU-bound, so it stays off the async workers
+        tasks.push(tokio::task::spawn_blocking(move || {
+            render_frame(i, frame, &finish, texture_image.as_ref(), &frame_decals)
+        }));
     }
 
+    let mut rendered = Vec::new();
+    let mut accuracy = Vec::new();
     for task in tasks {
-        task.await.unwrap();
+        let (frame, frame_accuracy) = task
+            .await
+            .map_err(|_| Error::ColorSwapError)?
+            .map_err(|e| {
+                eprintln!("Error recoloring frame {}", e);
+                Error::ColorSwapError
+            })?;
+        rendered.push(frame);
+        accuracy.extend(frame_accuracy);
     }
-
-    Ok(())
+    println!("applied hue shift");
+    Ok((rendered, accuracy))
 }
 
-async fn colorize_images(
-    base_i: &str,
-    base_o: &str,
-    target_color: [u8; 3],
-) -> Result<(), opencv::Error> {
+/// Downloads a base set frame through the frame cache, decoded with the given
+/// `imread` flags.
+pub async fn download_base_frame(frame: &BaseFrame, flags: i32) -> Result<Mat, Error> {
+    let img_bytes = frame_cache::fetch(frame).await?;
+    decode_frame(&img_bytes, flags)
+}
 
-    if !std::path::Path::new(&base_i).exists() {
-        println!("Error: Image '{}' does not exist.", base_i);
-    }
+/// Downloads an image straight into memory, decoded with the given `imread`
+/// flags. For textures, decals and renders, which are never cached.
+pub async fn download_frame(url: String, flags: i32) -> Result<Mat, Error> {
+    let img_bytes = fetcher().download(&url).await?;
+    decode_frame(&img_bytes, flags)
+}
 
+fn decode_frame(img_bytes: &[u8], flags: i32) -> Result<Mat, Error> {
+    // OpenCV has no pixel limit of its own, so the header is checked first
+    fetcher().check_dimensions(img_bytes)?;
 
-    match extract_desired_areas(&base_i) {
-        Ok(mask) => {
-            imgcodecs::imwrite(&base_o, &mask, &opencv::core::Vector::<i32>::new()).unwrap();
-            println!("Desired areas extraction successful");
-        }
-        Err(err) => println!("Error: {}", err),
+    let buffer = opencv::core::Vector::<u8>::from_slice(img_bytes);
+    match imgcodecs::imdecode(&buffer, flags) {
+        Ok(image) if !image.empty() => Ok(image),
+        _ => Err(Error::ColorSwapError),
     }
+}
 
-    let mut original_image = match imgcodecs::imread(&base_i, imgcodecs::IMREAD_COLOR) {
-        Ok(img) => img,
-        Err(err) => {
-            println!("Error reading image '{}' {}", base_i, err);
-            return Err(err)
-        }
-    };
+/// Downloads a decal as 8-bit BGRA, whatever kind of image it was stored as.
+pub async fn download_decal(url: String) -> Result<Mat, Error> {
+    let decal_image = download_frame(url, imgcodecs::IMREAD_UNCHANGED).await?;
+    decal_to_bgra(&decal_image).map_err(|_| Error::InvalidDecal)
+}
 
-    let mask = match imgcodecs::imread(&base_o, imgcodecs::IMREAD_GRAYSCALE) {
-        Ok(mask) => mask,
-        Err(err) => {
-            println!("Error reading mask {}", err);
-            return Err(err)
+/// Downloads an image into memory with the `image` crate, for work that does not go through OpenCV.
+pub async fn download_image(url: String) -> Result<image::DynamicImage, Error> {
+    let img_bytes = fetcher().download(&url).await?;
+    Ok(fetcher().decode(&img_bytes)?)
+}
 
-        }
-    };
-    let rgb = Rgb::from(target_color);
-    let hsv_target = Hsl::from(&rgb);
+/// Masks and paints one base frame, and scores it against the finish.
+fn render_frame(
+    frame_index: usize,
+    mut frame: Mat,
+    finish: &Finish,
+    texture_image: Option<&Mat>,
+    decals: &[(DecalPlacement, Mat)],
+) -> Result<(DynamicImage, Option<FrameAccuracy>), opencv::Error> {
+    let mask = desired_areas(&frame)?;
+    // The two steps of `recolor_frame`, scored in between: the decals are not
+    // meant to match the finish
+    let luminance = paint_finish(&mut frame, &mask, finish, texture_image)?;
+    let accuracy = score_frame(frame_index, &frame, &mask, finish)?;
+    paint_decals(&mut frame, &mask, &luminance, decals)?;
+
+    Ok((encoding::from_bgr(&frame)?, accuracy))
+}
 
-    let hsv_target_hue = hsv_target.hue() ;
-    let hsv_target_saturation = hsv_target.saturation();
-    let hsv_target_value = hsv_target.lightness();
+/// Paints one frame in memory: the finish goes into the masked area, then the
+/// decals are placed on top. Shared by the full render, the previews and the
+/// color wall.
+pub fn recolor_frame(
+    original_image: &mut Mat,
+    mask: &Mat,
+    finish: &Finish,
+    texture_image: Option<&Mat>,
+    decals: &[(DecalPlacement, Mat)],
+) -> Result<(), opencv::Error> {
+    let luminance = paint_finish(original_image, mask, finish, texture_image)?;
+    paint_decals(original_image, mask, &luminance, decals)
+}
 
-    apply_color_change(&mut original_image, &mask, hsv_target_hue, hsv_target_saturation, hsv_target_value);
+/// Paints the finish into the masked area. Returns the luminance of the frame
+/// before painting, which the decals are shaded with.
+fn paint_finish(
+    original_image: &mut Mat,
+    mask: &Mat,
+    finish: &Finish,
+    texture_image: Option<&Mat>,
+) -> Result<Mat, opencv::Error> {
+    let mut luminance = Mat::default();
+    imgproc::cvt_color(&*original_image, &mut luminance, imgproc::COLOR_BGR2GRAY, 0)?;
+    match finish {
+        Finish::Solid(target_color) => {
+            let target = PaintTarget::from(*target_color);
+            apply_color_change(original_image, mask, |_, _| target)?;
+        }
+        Finish::Gradient(gradient) => {
+            // Spread the gradient over the painted area rather than the whole frame,
+            // sampling it once into a lookup table instead of interpolating per pixel
+            let bounds = imgproc::bounding_rect(mask)?;
+            let lut: Vec<PaintTarget> = (0..=255)
+                .map(|i| PaintTarget::from(gradient.color_at(i as f64 / 255.0)))
+                .collect();
+            apply_color_change(original_image, mask, |x, y| {
+                let t = gradient.position(x - bounds.x, y - bounds.y, bounds.width, bounds.height);
+                lut[(t * 255.0).round() as usize]
+            })?;
+        }
+        Finish::Texture(texture) => {
+            let texture_image = texture_image.ok_or_else(|| {
+                opencv::Error::new(opencv::core::StsNullPtr, "Texture image was not loaded".to_string())
+            })?;
+            apply_texture(original_image, mask, &luminance, texture_image, texture)?;
+        }
+    }
+    Ok(luminance)
+}
 
-    imgcodecs::imwrite(&base_i, &original_image, &opencv::core::Vector::<i32>::new()).unwrap();
+/// Places the decals over the painted frame.
+fn paint_decals(
+    original_image: &mut Mat,
+    mask: &Mat,
+    luminance: &Mat,
+    decals: &[(DecalPlacement, Mat)],
+) -> Result<(), opencv::Error> {
+    for (decal, decal_image) in decals {
+        if decal_image.typ() == CV_8UC4 {
+            apply_decal(original_image, mask, luminance, decal_image, decal)?;
+        } else {
+            let bgra = decal_to_bgra(decal_image)?;
+            apply_decal(original_image, mask, luminance, &bgra, decal)?;
+        }
+    }
     Ok(())
 }
 
@@ -149,67 +253,108 @@ fn adjust_color(base_pixel: &Rgba<f32>, target_color: [u8; 3]) -> Rgba<f32> {
 }
 
 
-fn extract_desired_areas(image_path: &str) -> Result<Mat, opencv::Error> {
-    let image = imgcodecs::imread(image_path, imgcodecs::IMREAD_COLOR)?;
+/// Mask of the paint areas of a BGR frame.
+pub fn desired_areas(image: &Mat) -> Result<Mat, opencv::Error> {
+    desired_areas_with_profile(image, MaskProfile::Standard)
+}
 
+pub fn desired_areas_with_profile(image: &Mat, profile: MaskProfile) -> Result<Mat, opencv::Error> {
     let mut hsv_image = Mat::default();
-    imgproc::cvt_color(&image, &mut hsv_image, imgproc::COLOR_BGR2HSV, 0)?;
+    imgproc::cvt_color(image, &mut hsv_image, imgproc::COLOR_BGR2HSV, 0)?;
 
-    let lower_bound = Scalar::new(10.0, 1.0, 10.0, 0.0); // Lower bound for hue, saturation, and value
-    let upper_bound = Scalar::new(45.0, 255.0, 255.0, 255.0); // Upper bound for hue, saturation, and valu
+    let (lower_bound, upper_bound) = profile.bounds();
 
     let mut desired_mask = Mat::default();
     opencv::core::in_range(&hsv_image, &lower_bound, &upper_bound, &mut desired_mask)?;
 
-    let mut desired_mask_output = Mat::default();
-
-    let kernel = Mat::ones(1, 1, CV_8U)?;
-    imgproc::morphology_ex(
-        &desired_mask,
-        &mut desired_mask_output,
-        imgproc::MORPH_OPEN,
-        &kernel,
-        opencv::core::Point::new(-1, -1),
-        3,
-        opencv::core::BORDER_ISOLATED,
-        opencv::core::Scalar::default(),
-    )?;
-
-
-    Ok(desired_mask_output)
+    refine_mask(&desired_mask)
 }
 
-fn apply_color_change(original_image: &mut Mat, mask: &Mat, target_hue: f64, target_saturation: f64, target_value: f64) {
-    for y in 0..original_image.rows() {
-        for x in 0..original_image.cols() {
-            let mask_value = mask.at_2d::<u8>(y, x).unwrap();
-
-            if *mask_value == 255 {
-                let bgr_pixel = original_image.at_2d_mut::<Vec3b>(y, x).unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{Scalar, CV_16UC1, CV_8UC1, CV_8UC2, CV_8UC3};
 
-                let rgba = Rgb::new(bgr_pixel[2] as f64, bgr_pixel[1] as f64, bgr_pixel[0] as f64, None);
-                let mut hsla: Hsl = rgba.as_ref().into();
+    fn frame() -> (Mat, Mat) {
+        let frame = Mat::new_rows_cols_with_default(40, 60, CV_8UC3, Scalar::all(120.0)).unwrap();
+        let mask = Mat::new_rows_cols_with_default(40, 60, CV_8UC1, Scalar::all(255.0)).unwrap();
+        (frame, mask)
+    }
 
-                let original_lightness = hsla.lightness();
-                let delta_lightness = target_value - original_lightness;
-                let transformed_lightness = original_lightness + non_linear_transform(delta_lightness);
+    fn placement() -> DecalPlacement {
+        DecalPlacement {
+            frame: 0,
+            url: "decal.png".to_string(),
+            x: 0.5,
+            y: 0.5,
+            scale: 0.5,
+            rotation: 15.0,
+        }
+    }
 
-                hsla.set_hue(target_hue);
-                hsla.set_saturation(target_saturation);
-                hsla.set_lightness(transformed_lightness);
+    fn painted_with(decal_image: Mat) -> Mat {
+        let (mut frame, mask) = frame();
+        let finish = Finish::Solid([120, 120, 120]);
+        recolor_frame(
+            &mut frame,
+            &mask,
+            &finish,
+            None,
+            &[(placement(), decal_image)],
+        )
+        .unwrap();
+        frame
+    }
 
+    #[test]
+    fn converts_decals_to_bgra() {
+        for typ in [CV_8UC1, CV_8UC2, CV_8UC3, CV_16UC1] {
+            let decal_image =
+                Mat::new_rows_cols_with_default(10, 10, typ, Scalar::all(200.0)).unwrap();
+            let bgra = decal_to_bgra(&decal_image).unwrap();
+            assert_eq!(bgra.typ(), CV_8UC4, "type {}", typ);
+            assert_eq!(bgra.size().unwrap(), decal_image.size().unwrap());
+        }
+    }
 
-                let rgb_arr: [u8; 3] = Rgb::from(&hsla).into();
+    #[test]
+    fn places_grayscale_decals() {
+        let white = Mat::new_rows_cols_with_default(10, 10, CV_8UC1, Scalar::all(255.0)).unwrap();
+        let frame = painted_with(white);
+        let center = frame.at_2d::<opencv::core::Vec3b>(20, 30).unwrap();
+        assert!(center.0.iter().all(|&c| c > 200), "{:?}", center.0);
+        let corner = frame.at_2d::<opencv::core::Vec3b>(0, 0).unwrap();
+        // Outside the decal the finish is left alone
+        assert!(corner.0.iter().all(|&c| c < 200), "{:?}", corner.0);
+    }
 
-                bgr_pixel[0] = rgb_arr[2];
-                bgr_pixel[1] = rgb_arr[1];
-                bgr_pixel[2] = rgb_arr[0];
-            }
-        }
+    #[test]
+    fn places_16_bit_decals() {
+        let white =
+            Mat::new_rows_cols_with_default(10, 10, CV_16UC1, Scalar::all(65535.0)).unwrap();
+        let frame = painted_with(white);
+        let center = frame.at_2d::<opencv::core::Vec3b>(20, 30).unwrap();
+        assert!(center.0.iter().all(|&c| c > 200), "{:?}", center.0);
     }
-}
 
-fn non_linear_transform(delta_lightness: f64) -> f64 {
-    delta_lightness.signum() * delta_lightness.abs().powf(0.87)
+    #[test]
+    fn full_render_paints_like_recolor_frame() {
+        let red = Scalar::new(30.0, 30.0, 200.0, 0.0);
+        let frame = Mat::new_rows_cols_with_default(40, 60, CV_8UC3, red).unwrap();
+        let finish = Finish::Solid([20, 160, 60]);
+        let white = Mat::new_rows_cols_with_default(10, 10, CV_8UC1, Scalar::all(255.0)).unwrap();
+        let decals = vec![(placement(), white)];
+
+        let (rendered, _) =
+            render_frame(0, frame.try_clone().unwrap(), &finish, None, &decals).unwrap();
+        let mut recolored = frame.try_clone().unwrap();
+        let mask = desired_areas(&frame).unwrap();
+        recolor_frame(&mut recolored, &mask, &finish, None, &decals).unwrap();
+
+        let recolored = encoding::from_bgr(&recolored).unwrap();
+        assert_eq!(
+            rendered.to_rgba8().into_raw(),
+            recolored.to_rgba8().into_raw()
+        );
+    }
 }
-
@@ -0,0 +1,185 @@
+use crate::db::Connection;
+use crate::functionality::accuracy::score_frame;
+use crate::functionality::color_swap::{desired_areas, download_base_frame, recolor_frame};
+use crate::functionality::{container_generation, encoding};
+use crate::handle_errors::Error;
+use crate::storage::Storage;
+use crate::types::accuracy::FrameAccuracy;
+use crate::types::color_wall::{ColorWall, ColorWallEntry, ColorWallStatus, WallColor};
+use crate::types::finish::Finish;
+use crate::types::frame::BaseFrame;
+use crate::types::image::{Image, NewImage};
+use crate::types::output::OutputOptions;
+use image::DynamicImage;
+use opencv::core::Mat;
+use opencv::imgcodecs;
+
+/// Renders an image set in every color of a color wall job, recording each
+/// render in the job's index as it finishes. Meant to run in the background;
+/// failures of single colors are recorded in the index and the job goes on.
+pub async fn run_color_wall(
+    db: Connection,
+    storage: Storage,
+    wall: ColorWall,
+    base_frames: Vec<BaseFrame>,
+    colors: Vec<WallColor>,
+    userid: Option<i32>,
+    output: OutputOptions,
+) {
+    // Frames are downloaded and masked once, masks do not depend on the color
+    let mut frames = match prepare_frames(base_frames).await {
+        Ok(frames) => frames,
+        Err(e) => {
+            eprintln!("Error preparing color wall {} {}", wall.id, e);
+            if let Err(e) = db
+                .set_color_wall_status(wall.id, ColorWallStatus::Failed)
+                .await
+            {
+                eprintln!("Database error {:?}", e);
+            }
+            return;
+        }
+    };
+
+    let total = colors.len();
+    for (i, color) in colors.into_iter().enumerate() {
+        // Recoloring is CPU-bound, so it runs off the async workers; the
+        // frames go along and come back for the next color
+        let finish = Finish::Solid(color.colors);
+        let task = tokio::task::spawn_blocking(move || {
+            let recolored = recolor_frames(&frames, &finish);
+            (frames, recolored)
+        });
+        let recolored = match task.await {
+            Ok((returned, recolored)) => {
+                frames = returned;
+                recolored
+            }
+            Err(e) => {
+                eprintln!("Error rendering color wall {} {}", wall.id, e);
+                if let Err(e) = db
+                    .set_color_wall_status(wall.id, ColorWallStatus::Failed)
+                    .await
+                {
+                    eprintln!("Database error {:?}", e);
+                }
+                return;
+            }
+        };
+        let result = match recolored {
+            Ok((rendered, accuracy)) => {
+                save_render(
+                    &db, &storage, &wall, rendered, accuracy, &color, userid, &output,
+                )
+                .await
+            }
+            Err(e) => Err(e.to_string()),
+        };
+        let entry = match result {
+            Ok(image) => ColorWallEntry {
+                color,
+                imageid: Some(image.id),
+                url: image.url,
+                accuracy: image.accuracy,
+                error: None,
+            },
+            Err(e) => {
+                eprintln!(
+                    "Error rendering color wall {} color {:?} {}",
+                    wall.id, color.colors, e
+                );
+                ColorWallEntry {
+                    color,
+                    imageid: None,
+                    url: Vec::new(),
+                    accuracy: Vec::new(),
+                    error: Some(e),
+                }
+            }
+        };
+        if let Err(e) = db.add_color_wall_entry(wall.id, entry).await {
+            eprintln!("Database error {:?}", e);
+        }
+        println!("Color wall {}: {}/{} colors", wall.id, i + 1, total);
+    }
+
+    if let Err(e) = db
+        .set_color_wall_status(wall.id, ColorWallStatus::Done)
+        .await
+    {
+        eprintln!("Database error {:?}", e);
+    }
+}
+
+async fn prepare_frames(base_frames: Vec<BaseFrame>) -> Result<Vec<(Mat, Mat)>, Error> {
+    let mut downloaded = Vec::new();
+    for base_frame in &base_frames {
+        downloaded.push(download_base_frame(base_frame, imgcodecs::IMREAD_COLOR).await?);
+    }
+    // Masking is CPU-bound as well, so it runs off the async workers like the recoloring
+    tokio::task::spawn_blocking(move || {
+        let mut frames = Vec::new();
+        for frame in downloaded {
+            let mask = desired_areas(&frame).map_err(|_| Error::ColorSwapError)?;
+            frames.push((frame, mask));
+        }
+        Ok(frames)
+    })
+    .await
+    .map_err(|_| Error::ColorSwapError)?
+}
+
+async fn save_render(
+    db: &Connection,
+    storage: &Storage,
+    wall: &ColorWall,
+    rendered: Vec<DynamicImage>,
+    accuracy: Vec<FrameAccuracy>,
+    color: &WallColor,
+    userid: Option<i32>,
+    output: &OutputOptions,
+) -> Result<Image, String> {
+    let id = db.reserve_image_id().await.map_err(|e| e.to_string())?;
+    let prefix = container_generation::render_prefix(storage, userid, id.0);
+    let upload = container_generation::upload_frames(storage, &prefix, rendered, output)
+        .await
+        .map_err(|e| e.to_string())?;
+    let new_image = NewImage {
+        url: upload
+            .frames
+            .iter()
+            .filter_map(|frame| frame.largest_url())
+            .collect(),
+        frame_count: upload.frames.len() as i32,
+        base_imageid: Some(wall.imageid.0),
+        colors: color.colors,
+        userid,
+        variants: upload.frames,
+        sprite_sheet: upload.sprite_sheet,
+        accuracy,
+    };
+    match db.add_new_image(id, new_image).await {
+        Ok(image) => Ok(image),
+        Err(e) => {
+            if let Err(e) = container_generation::remove_render(storage, &prefix).await {
+                eprintln!("Error rolling back render {} {}", prefix, e);
+            }
+            Err(e.to_string())
+        }
+    }
+}
+
+fn recolor_frames(
+    frames: &[(Mat, Mat)],
+    finish: &Finish,
+) -> Result<(Vec<DynamicImage>, Vec<FrameAccuracy>), opencv::Error> {
+    let mut rendered = Vec::new();
+    let mut accuracy = Vec::new();
+    for (i, (frame, mask)) in frames.iter().enumerate() {
+        let mut recolored = frame.try_clone()?;
+        recolor_frame(&mut recolored, mask, finish, None, &[])?;
+        accuracy.extend(score_frame(i, &recolored, mask, finish)?);
+        rendered.push(encoding::from_bgr(&recolored)?);
+    }
+    Ok((rendered, accuracy))
+}
@@ -0,0 +1,172 @@
+use crate::functionality::color_swap::{download_base_frame, download_frame};
+use crate::handle_errors::Error;
+use crate::types::comparison::{ComparisonLayout, ComparisonQuery};
+use crate::types::frame::BaseFrame;
+use crate::types::preview::PreviewFormat;
+use opencv::core::{Mat, MatTraitConst, Point, Rect, Scalar, Size, Vector, CV_8U};
+use opencv::{imgcodecs, imgproc};
+
+const CAPTION_FONT: i32 = imgproc::FONT_HERSHEY_SIMPLEX;
+const CAPTION_MARGIN: i32 = 12;
+const DIVIDER_THICKNESS: i32 = 3;
+
+/// Puts a base frame and its recolored version into one image and returns it encoded.
+pub async fn compare_frames(
+    base_frame: BaseFrame,
+    recolored_url: String,
+    query: &ComparisonQuery,
+    after_caption: Option<String>,
+) -> Result<Vec<u8>, Error> {
+    let before = download_base_frame(&base_frame, imgcodecs::IMREAD_COLOR).await?;
+    let after = download_frame(recolored_url, imgcodecs::IMREAD_COLOR).await?;
+
+    let composed = compose(&before, &after, query, after_caption.as_deref())
+        .map_err(|_| Error::ColorSwapError)?;
+
+    let mut params = Vector::<i32>::new();
+    if query.format == PreviewFormat::Webp {
+        params.push(imgcodecs::IMWRITE_WEBP_QUALITY);
+        params.push(90);
+    }
+    let mut encoded = Vector::<u8>::new();
+    imgcodecs::imencode(query.format.extension(), &composed, &mut encoded, &params)
+        .map_err(|_| Error::ColorSwapError)?;
+    Ok(encoded.to_vec())
+}
+
+fn compose(
+    before: &Mat,
+    after: &Mat,
+    query: &ComparisonQuery,
+    after_caption: Option<&str>,
+) -> Result<Mat, opencv::Error> {
+    let width = before.cols();
+    let height = before.rows();
+
+    // Renders can be a smaller variant than the base frame
+    let mut after_resized = Mat::default();
+    imgproc::resize(
+        after,
+        &mut after_resized,
+        Size::new(width, height),
+        0.0,
+        0.0,
+        imgproc::INTER_AREA,
+    )?;
+
+    let white = Scalar::all(255.0);
+    let (mut composed, after_origin) = match query.layout {
+        ComparisonLayout::SideBySide => {
+            let mut composed = Mat::default();
+            opencv::core::hconcat2(before, &after_resized, &mut composed)?;
+            (composed, Point::new(width, 0))
+        }
+        ComparisonLayout::Diagonal => {
+            let corners = [
+                Point::new(width, 0),
+                Point::new(width, height),
+                Point::new(0, height),
+            ];
+            let mut composed = before.try_clone()?;
+            copy_inside(&after_resized, &mut composed, &corners)?;
+            imgproc::line(
+                &mut composed,
+                corners[0],
+                corners[2],
+                white,
+                DIVIDER_THICKNESS,
+                imgproc::LINE_AA,
+                0,
+            )?;
+            (composed, Point::new(width / 2, height / 2))
+        }
+        ComparisonLayout::Slider => {
+            let x = (width as f64 * query.split).round() as i32;
+            let corners = [
+                Point::new(x, 0),
+                Point::new(width, 0),
+                Point::new(width, height),
+                Point::new(x, height),
+            ];
+            let mut composed = before.try_clone()?;
+            copy_inside(&after_resized, &mut composed, &corners)?;
+            imgproc::line(
+                &mut composed,
+                corners[0],
+                corners[3],
+                white,
+                DIVIDER_THICKNESS,
+                imgproc::LINE_AA,
+                0,
+            )?;
+            imgproc::circle(
+                &mut composed,
+                Point::new(x, height / 2),
+                18,
+                white,
+                imgproc::FILLED,
+                imgproc::LINE_AA,
+                0,
+            )?;
+            (composed, Point::new(x, 0))
+        }
+    };
+
+    if query.captions {
+        draw_caption(&mut composed, "Before", Point::new(0, 0))?;
+        draw_caption(
+            &mut composed,
+            after_caption.unwrap_or("After"),
+            after_origin,
+        )?;
+    }
+    Ok(composed)
+}
+
+fn copy_inside(
+    source: &Mat,
+    destination: &mut Mat,
+    polygon: &[Point],
+) -> Result<(), opencv::Error> {
+    let mut mask = Mat::zeros(destination.rows(), destination.cols(), CV_8U)?.to_mat()?;
+    let points = Vector::<Point>::from_iter(polygon.iter().copied());
+    imgproc::fill_convex_poly(&mut mask, &points, Scalar::all(255.0), imgproc::LINE_8, 0)?;
+    source.copy_to_masked(destination, &mask)
+}
+
+/// Writes `text` on a dark box in the top-left corner of the area starting at `origin`.
+fn draw_caption(image: &mut Mat, text: &str, origin: Point) -> Result<(), opencv::Error> {
+    let scale = (image.rows() as f64 / 900.0).max(0.5);
+    let thickness = (scale * 2.0).round() as i32;
+    let mut baseline = 0;
+    let text_size = imgproc::get_text_size(text, CAPTION_FONT, scale, thickness, &mut baseline)?;
+
+    let background = Rect::new(
+        origin.x + CAPTION_MARGIN,
+        origin.y + CAPTION_MARGIN,
+        text_size.width + CAPTION_MARGIN * 2,
+        text_size.height + baseline + CAPTION_MARGIN * 2,
+    );
+    imgproc::rectangle(
+        image,
+        background,
+        Scalar::all(0.0),
+        imgproc::FILLED,
+        imgproc::LINE_8,
+        0,
+    )?;
+    imgproc::put_text(
+        image,
+        text,
+        Point::new(
+            background.x + CAPTION_MARGIN,
+            background.y + CAPTION_MARGIN + text_size.height,
+        ),
+        CAPTION_FONT,
+        scale,
+        Scalar::all(255.0),
+        thickness,
+        imgproc::LINE_AA,
+        false,
+    )
+}
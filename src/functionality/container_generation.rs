@@ -1,77 +1,299 @@
-use azure_core::auth::Secret;
-use azure_storage::prelude::*;
-use azure_storage::shared_access_signature::service_sas::BlobSharedAccessSignature;
-use azure_storage_blobs::prelude::*;
-use std::fs::File;
-use std::io::Read;
-use time::{Duration, OffsetDateTime};
-use walkdir::WalkDir;
-
-const ACCOUNT: &str = "wrapmycar";
-pub async fn generate_and_upload(container_name: String) -> azure_core::Result<Vec<String>> {
-    let access_key_string =
-        std::fs::read_to_string("src/key.txt").expect("Failed to read access key from key.txt");
-    let access_key_trimmed = access_key_string.trim().to_string();
-    let storage_credentials = StorageCredentials::access_key(ACCOUNT, access_key_trimmed.clone());
-    let client = ClientBuilder::new(ACCOUNT, storage_credentials);
-    client
-        .clone()
-        .container_client(&container_name)
-        .create()
-        .public_access(PublicAccess::Blob)
-        .await?;
-
-    let base_folder = "src/base/";
-    let mut image_file_names: Vec<_> = WalkDir::new(base_folder)
-        .into_iter()
-        .filter_map(|entry| {
-            let entry = entry.unwrap();
-            if entry.file_type().is_file() {
-                Some(entry.path().to_string_lossy().into_owned())
-            } else {
-                None
+use crate::functionality::encoding;
+use crate::handle_errors::{Error, StorageError};
+use crate::storage::{split_key, Storage};
+use crate::types::decal::DecalPlacement;
+use crate::types::finish::Finish;
+use crate::types::output::{FrameVariants, OutputOptions, RenderUpload, SpriteSheet, Variant};
+use futures::stream::{self, StreamExt};
+use image::DynamicImage;
+use rand::Rng;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+pub const TEXTURE_CONTAINER: &str = "textures";
+pub const DECAL_CONTAINER: &str = "decals";
+
+/// Uploads of one render that run at the same time.
+const UPLOAD_CONCURRENCY: usize = 8;
+/// Attempts per object before a render is given up on.
+const UPLOAD_ATTEMPTS: u32 = 5;
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(5);
+
+/// Key prefix of one render in the render container:
+/// `{container}/renders/{user}/{image_id}`, with `anonymous` for renders
+/// that were not made by a signed-in user.
+pub fn render_prefix(storage: &Storage, userid: Option<i32>, imageid: i32) -> String {
+    let user = userid
+        .map(|userid| userid.to_string())
+        .unwrap_or_else(|| "anonymous".to_string());
+    format!("{}/renders/{}/{}", storage.container(), user, imageid)
+}
+
+/// Uploads rendered frames under `prefix`, as `{size}/{frame}.{ext}` for every
+/// requested size, plus the optional sprite sheet and its manifest. Everything
+/// is encoded first and then uploaded concurrently; if any upload still fails
+/// after its retries, whatever was uploaded under `prefix` is removed again.
+pub async fn upload_frames(
+    storage: &Storage,
+    prefix: &str,
+    frames: Vec<DynamicImage>,
+    output: &OutputOptions,
+) -> Result<RenderUpload, StorageError> {
+    // Resizing and encoding are CPU-bound, so they stay off the async workers
+    let (encode_prefix, encode_output) = (prefix.to_string(), output.clone());
+    let (uploads, upload) =
+        tokio::task::spawn_blocking(move || encode_render(&encode_prefix, &frames, &encode_output))
+            .await
+            .map_err(|e| StorageError::InvalidData(e.to_string()))??;
+
+    if let Err(e) = upload_all(storage, uploads).await {
+        eprintln!("Error uploading render {}, rolling back {}", e, prefix);
+        if let Err(e) = remove_render(storage, prefix).await {
+            eprintln!("Error rolling back render {} {}", prefix, e);
+        }
+        return Err(e);
+    }
+
+    Ok(upload)
+}
+
+/// Encodes every object `upload_frames` stores for a render.
+fn encode_render(
+    prefix: &str,
+    frames: &[DynamicImage],
+    output: &OutputOptions,
+) -> Result<(Vec<PendingUpload>, RenderUpload), StorageError> {
+    let mut uploads = Vec::new();
+    let mut res: Vec<FrameVariants> = vec![];
+    let mut sprite_frames = Vec::new();
+    for (frame, rendered) in frames.iter().enumerate() {
+        let mut variants = Vec::new();
+        for size in &output.sizes {
+            let resized = encoding::resize_variant(rendered, *size);
+            let image_data = encoding::encode(&resized, output.format, output.quality)
+                .map_err(|e| StorageError::InvalidData(e.to_string()))?;
+            let key = format!(
+                "{}/{}/{}.{}",
+                prefix,
+                size.name(),
+                frame,
+                output.format.extension()
+            );
+
+            uploads.push(PendingUpload {
+                key: key.clone(),
+                content_type: output.format.content_type(),
+                data: image_data,
+            });
+            variants.push(Variant {
+                size: *size,
+                format: output.format,
+                width: resized.width(),
+                height: resized.height(),
+                url: key,
+            });
+        }
+        res.push(FrameVariants { frame, variants });
+
+        if let Some(size) = output.sprite_sheet {
+            sprite_frames.push(encoding::resize_variant(rendered, size));
+        }
+    }
+
+    let sprite_sheet = match output.sprite_sheet {
+        Some(size) if !sprite_frames.is_empty() => {
+            let (sheet, columns, rows, frames) = encoding::sprite_sheet(&sprite_frames);
+            let image_data = encoding::encode(&sheet, output.format, output.quality)
+                .map_err(|e| StorageError::InvalidData(e.to_string()))?;
+            let key = format!(
+                "{}/sprite_{}.{}",
+                prefix,
+                size.name(),
+                output.format.extension()
+            );
+            uploads.push(PendingUpload {
+                key: key.clone(),
+                content_type: output.format.content_type(),
+                data: image_data,
+            });
+
+            let manifest = SpriteSheet {
+                url: key,
+                format: output.format,
+                width: sheet.width(),
+                height: sheet.height(),
+                columns,
+                rows,
+                frames,
+            };
+            let manifest_data = serde_json::to_vec(&manifest)
+                .map_err(|e| StorageError::InvalidData(e.to_string()))?;
+            uploads.push(PendingUpload {
+                key: format!("{}/sprite.json", prefix),
+                content_type: "application/json",
+                data: manifest_data,
+            });
+            Some(manifest)
+        }
+        _ => None,
+    };
+
+    Ok((
+        uploads,
+        RenderUpload {
+            frames: res,
+            sprite_sheet,
+        },
+    ))
+}
+
+/// Deletes every object stored under a render prefix.
+pub async fn remove_render(storage: &Storage, prefix: &str) -> Result<(), StorageError> {
+    let (container, path) = split_key(prefix)?;
+    // The trailing slash keeps `.../12` from also matching `.../123`
+    for object in storage.list(container, &format!("{}/", path)).await? {
+        storage.delete(&object.key).await?;
+    }
+    Ok(())
+}
+
+struct PendingUpload {
+    key: String,
+    content_type: &'static str,
+    data: Vec<u8>,
+}
+
+/// Runs the uploads, at most `UPLOAD_CONCURRENCY` at a time. Once one has
+/// failed for good no new ones are started, but those already running are
+/// waited for, so nothing is still being written when the caller rolls back.
+async fn upload_all(storage: &Storage, uploads: Vec<PendingUpload>) -> Result<(), StorageError> {
+    let failed = AtomicBool::new(false);
+    let results: Vec<Result<(), StorageError>> = stream::iter(uploads)
+        .map(|upload| {
+            let failed = &failed;
+            async move {
+                if failed.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                let result = put_with_retry(storage, upload).await;
+                if result.is_err() {
+                    failed.store(true, Ordering::Relaxed);
+                }
+                result
             }
         })
-        .collect();
-
-    image_file_names.sort_by(|a, b| natord::compare_ignore_case(a, b));
-
-    let mut res: Vec<String> = vec![];
-    for image_file_name in &image_file_names {
-        println!("file {}", image_file_name);
-        let mut file = File::open(&image_file_name).expect("Failed to open image file");
-        let mut image_data = Vec::new();
-        file.read_to_end(&mut image_data)
-            .expect("Failed to read image file");
-        let blob_name = image_file_name
-            .trim_start_matches(base_folder)
-            .trim_start_matches('/')
-            .to_string();
-
-        let blob_client = client.clone().blob_client(&container_name, &blob_name);
-
-        blob_client
-            .put_block_blob(image_data)
-            .content_type("image/png")
-            .await?;
-
-        let resource: String = format!("/blob/{}/{}/{}", ACCOUNT, container_name, blob_name);
-        let permissions = BlobSasPermissions {
-            read: true,
-            ..Default::default()
-        };
-        let signed_token = BlobSharedAccessSignature::new(
-            Secret::new(access_key_trimmed.clone()),
-            resource,
-            permissions,
-            OffsetDateTime::now_utc() + Duration::days(30),
-            BlobSignedResource::Blob,
-        );
-
-        let sas_token = blob_client
-            .generate_signed_blob_url(&signed_token)
-            .expect("Failed to generate SAS token");
-        res.push(sas_token.to_string());
+        .buffer_unordered(UPLOAD_CONCURRENCY)
+        .collect()
+        .await;
+    results.into_iter().collect()
+}
+
+async fn put_with_retry(storage: &Storage, upload: PendingUpload) -> Result<(), StorageError> {
+    let mut attempt = 0;
+    loop {
+        match storage
+            .put(&upload.key, upload.content_type, upload.data.clone())
+            .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e) if e.is_retryable() && attempt + 1 < UPLOAD_ATTEMPTS => {
+                let delay = retry_delay(attempt);
+                eprintln!(
+                    "Error uploading {} {}, retrying in {}ms",
+                    upload.key,
+                    e,
+                    delay.as_millis()
+                );
+                tokio::time::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Exponential backoff with full jitter: a random delay up to
+/// `RETRY_BASE_DELAY * 2^attempt`, capped at `RETRY_MAX_DELAY`.
+fn retry_delay(attempt: u32) -> Duration {
+    let ceiling = RETRY_BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt))
+        .min(RETRY_MAX_DELAY);
+    let millis = rand::thread_rng().gen_range(0..=ceiling.as_millis() as u64);
+    Duration::from_millis(millis)
+}
+
+/// Uploads a single public file that is not part of a render (textures,
+/// decals) under a fresh name and returns its public URL.
+pub async fn upload_asset(
+    storage: &Storage,
+    container_name: &str,
+    extension: &str,
+    content_type: &'static str,
+    image_data: Vec<u8>,
+) -> Result<String, StorageError> {
+    storage.ensure_container(container_name, true).await?;
+
+    let key = format!("{}/{}.{}", container_name, uuid::Uuid::new_v4(), extension);
+    put_with_retry(
+        storage,
+        PendingUpload {
+            key: key.clone(),
+            content_type,
+            data: image_data,
+        },
+    )
+    .await?;
+
+    storage.public_url(&key)
+}
+
+/// Uploads an animation of an image under the image's render prefix, as
+/// `{prefix}/animations/{name}.{ext}`, and returns its key. Like the frames it
+/// is private, signed when handed out and deleted with the render.
+pub async fn upload_animation(
+    storage: &Storage,
+    prefix: &str,
+    extension: &str,
+    content_type: &'static str,
+    data: Vec<u8>,
+) -> Result<String, StorageError> {
+    let key = format!(
+        "{}/animations/{}.{}",
+        prefix,
+        uuid::Uuid::new_v4(),
+        extension
+    );
+    put_with_retry(
+        storage,
+        PendingUpload {
+            key: key.clone(),
+            content_type,
+            data,
+        },
+    )
+    .await?;
+    Ok(key)
+}
+
+/// Makes sure the texture and decals of a request are assets uploaded through
+/// `POST /textures` and `POST /decals`, so a request cannot have the server
+/// download anything else from storage.
+pub fn check_assets(
+    storage: &Storage,
+    finish: &Finish,
+    decals: &[DecalPlacement],
+) -> Result<(), Error> {
+    if let Finish::Texture(texture) = finish {
+        if !storage.is_public_asset(TEXTURE_CONTAINER, &texture.url) {
+            return Err(Error::InvalidTexture);
+        }
+    }
+    if decals
+        .iter()
+        .any(|decal| !storage.is_public_asset(DECAL_CONTAINER, &decal.url))
+    {
+        return Err(Error::InvalidDecal);
     }
-    Ok(res)
+    Ok(())
 }
@@ -0,0 +1,101 @@
+use crate::types::output::{OutputFormat, SpriteFrame, VariantSize};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::{WebPEncoder, WebPQuality};
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageEncoder, ImageResult, RgbImage};
+use opencv::core::{Mat, MatTraitConst};
+
+const AVIF_SPEED: u8 = 6;
+/// Largest width and height of a sprite sheet. WebP cannot go past 16383
+/// pixels, and 8192 is a texture size every current browser supports.
+const MAX_SPRITE_SHEET_SIZE: u32 = 8192;
+
+/// Copies a BGR frame from OpenCV into an RGB image the encoders can take.
+pub fn from_bgr(frame: &Mat) -> Result<DynamicImage, opencv::Error> {
+    let frame = frame.try_clone()?;
+    let rgb: Vec<u8> = frame
+        .data_bytes()?
+        .chunks_exact(3)
+        .flat_map(|bgr| [bgr[2], bgr[1], bgr[0]])
+        .collect();
+    let image = RgbImage::from_raw(frame.cols() as u32, frame.rows() as u32, rgb).ok_or_else(|| {
+        opencv::Error::new(opencv::core::StsBadSize, "Frame is not 8-bit BGR".to_string())
+    })?;
+    Ok(DynamicImage::ImageRgb8(image))
+}
+
+/// Scales a rendered frame to one of the variant sizes. Frames are never scaled up.
+pub fn resize_variant(frame: &DynamicImage, size: VariantSize) -> DynamicImage {
+    match size.width() {
+        Some(width) if width < frame.width() => {
+            let height = (frame.height() as f64 * width as f64 / frame.width() as f64).round() as u32;
+            frame.resize_exact(width, height.max(1), FilterType::Lanczos3)
+        }
+        _ => frame.clone(),
+    }
+}
+
+pub fn encode(frame: &DynamicImage, format: OutputFormat, quality: u8) -> ImageResult<Vec<u8>> {
+    let rgb = frame.to_rgb8();
+    let (width, height) = rgb.dimensions();
+    let color = image::ColorType::Rgb8;
+    let mut buffer = Vec::new();
+
+    match format {
+        OutputFormat::Png => PngEncoder::new(&mut buffer).write_image(&rgb, width, height, color)?,
+        OutputFormat::Jpeg => JpegEncoder::new_with_quality(&mut buffer, quality)
+            .write_image(&rgb, width, height, color)?,
+        OutputFormat::Webp => WebPEncoder::new_with_quality(&mut buffer, WebPQuality::lossy(quality))
+            .write_image(&rgb, width, height, color)?,
+        OutputFormat::Avif => AvifEncoder::new_with_speed_quality(&mut buffer, AVIF_SPEED, quality)
+            .write_image(&rgb, width, height, color)?,
+    }
+    Ok(buffer)
+}
+
+/// Lays the frames out in a grid that is as close to square as possible. When
+/// the grid would be larger than `MAX_SPRITE_SHEET_SIZE` on either side, every
+/// cell is scaled down by the same factor until it fits.
+pub fn sprite_sheet(frames: &[DynamicImage]) -> (DynamicImage, u32, u32, Vec<SpriteFrame>) {
+    let count = frames.len().max(1) as u32;
+    let columns = (count as f64).sqrt().ceil() as u32;
+    let rows = (count + columns - 1) / columns;
+    let cell_width = frames.iter().map(|frame| frame.width()).max().unwrap_or(0);
+    let cell_height = frames.iter().map(|frame| frame.height()).max().unwrap_or(0);
+
+    let limit = MAX_SPRITE_SHEET_SIZE as f64;
+    let scale = (limit / (columns * cell_width).max(1) as f64)
+        .min(limit / (rows * cell_height).max(1) as f64)
+        .min(1.0);
+    // Rounded down, so the scaled cells never add up to more than the limit
+    let scaled = |size: u32| ((size as f64 * scale).floor() as u32).max(1);
+    let (cell_width, cell_height) = if scale < 1.0 {
+        (scaled(cell_width), scaled(cell_height))
+    } else {
+        (cell_width, cell_height)
+    };
+
+    let mut sheet = RgbImage::new(columns * cell_width, rows * cell_height);
+    let mut layout = Vec::new();
+    for (i, frame) in frames.iter().enumerate() {
+        let frame = if scale < 1.0 {
+            frame.resize_exact(scaled(frame.width()), scaled(frame.height()), FilterType::Lanczos3)
+        } else {
+            frame.clone()
+        };
+        let x = (i as u32 % columns) * cell_width;
+        let y = (i as u32 / columns) * cell_height;
+        image::imageops::replace(&mut sheet, &frame.to_rgb8(), x as i64, y as i64);
+        layout.push(SpriteFrame {
+            frame: i,
+            x,
+            y,
+            width: frame.width(),
+            height: frame.height(),
+            angle: 360.0 * i as f64 / count as f64,
+        });
+    }
+    (DynamicImage::ImageRgb8(sheet), columns, rows, layout)
+}
@@ -0,0 +1,286 @@
+use crate::config::{Config, FetchConfig};
+use crate::handle_errors::FetchError;
+use image::io::{Limits, Reader};
+use image::DynamicImage;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, ETAG, LAST_MODIFIED};
+use reqwest::{redirect, StatusCode, Url};
+use std::io::Cursor;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Redirects followed before a download is given up on.
+const MAX_REDIRECTS: usize = 5;
+
+static FETCHER: OnceLock<Fetcher> = OnceLock::new();
+
+/// Sets up the process-wide fetcher. Without it, the first download sets one
+/// up from the environment.
+pub fn init(config: &FetchConfig) {
+    let _ = FETCHER.set(Fetcher::new(config));
+}
+
+pub fn fetcher() -> &'static Fetcher {
+    FETCHER.get_or_init(|| Fetcher::new(&Config::from_env().fetch))
+}
+
+/// Downloads source images with one shared client: only from allowed hosts,
+/// within timeouts and a size limit, and only if the body is an image.
+pub struct Fetcher {
+    client: reqwest::Client,
+    allowed_hosts: Vec<String>,
+    read_timeout: Duration,
+    max_bytes: u64,
+    max_pixels: u64,
+}
+
+/// A downloaded body with the validators a cache needs to revalidate it.
+pub struct Fetched {
+    pub data: Vec<u8>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl Fetcher {
+    pub fn new(config: &FetchConfig) -> Fetcher {
+        // Redirects are checked against the allowlist like the first URL
+        let allowed_hosts = config.allowed_hosts.clone();
+        let redirects = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if check_url(attempt.url(), &allowed_hosts).is_err() {
+                attempt.stop()
+            } else {
+                attempt.follow()
+            }
+        });
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_secs(config.connect_timeout_secs))
+            .timeout(Duration::from_secs(config.total_timeout_secs))
+            .redirect(redirects)
+            .build()
+            .expect("Failed to build HTTP client");
+        Fetcher {
+            client,
+            allowed_hosts: config.allowed_hosts.clone(),
+            read_timeout: Duration::from_secs(config.read_timeout_secs),
+            max_bytes: config.max_bytes,
+            max_pixels: config.max_pixels,
+        }
+    }
+
+    /// Parses a URL and makes sure it may be fetched from.
+    pub fn check(&self, url: &str) -> Result<Url, FetchError> {
+        let parsed = Url::parse(url).map_err(|_| FetchError::InvalidUrl(url.to_string()))?;
+        check_url(&parsed, &self.allowed_hosts)?;
+        Ok(parsed)
+    }
+
+    /// GETs an image with extra request `headers`. `None` means the host
+    /// answered `304 Not Modified` to a conditional request.
+    pub async fn get(&self, url: &str, headers: HeaderMap) -> Result<Option<Fetched>, FetchError> {
+        let mut response = self
+            .client
+            .get(self.check(url)?)
+            .headers(headers)
+            .send()
+            .await?;
+        let status = response.status();
+        if status == StatusCode::NOT_MODIFIED {
+            return Ok(None);
+        }
+        if !status.is_success() {
+            return Err(FetchError::Status(status.as_u16()));
+        }
+
+        let header = |name: reqwest::header::HeaderName| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string())
+        };
+        // Blob storage often serves images as plain binary data
+        if let Some(content_type) = header(CONTENT_TYPE) {
+            if !content_type.starts_with("image/")
+                && !content_type.starts_with("application/octet-stream")
+            {
+                return Err(FetchError::ContentType(content_type));
+            }
+        }
+        let etag = header(ETAG);
+        let last_modified = header(LAST_MODIFIED);
+        if response.content_length().unwrap_or(0) > self.max_bytes {
+            return Err(FetchError::TooLarge(self.max_bytes));
+        }
+
+        // Read in chunks, so an endless or stalled body is cut off
+        let mut data = Vec::new();
+        loop {
+            let chunk = tokio::time::timeout(self.read_timeout, response.chunk())
+                .await
+                .map_err(|_| FetchError::Timeout)??;
+            let Some(chunk) = chunk else {
+                break;
+            };
+            if (data.len() + chunk.len()) as u64 > self.max_bytes {
+                return Err(FetchError::TooLarge(self.max_bytes));
+            }
+            data.extend_from_slice(&chunk);
+        }
+        Ok(Some(Fetched {
+            data,
+            etag,
+            last_modified,
+        }))
+    }
+
+    /// GETs an image unconditionally, for images that are not cached.
+    pub async fn download(&self, url: &str) -> Result<Vec<u8>, FetchError> {
+        match self.get(url, HeaderMap::new()).await? {
+            Some(fetched) => Ok(fetched.data),
+            // Without validators sent, a 304 is not an answer
+            None => Err(FetchError::Status(304)),
+        }
+    }
+
+    /// Rejects images that would decode to more pixels than allowed, from
+    /// their header alone, before anything is decoded.
+    pub fn check_dimensions(&self, data: &[u8]) -> Result<(), FetchError> {
+        let (width, height) = Reader::new(Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| FetchError::Decode(e.to_string()))?
+            .into_dimensions()
+            .map_err(|e| FetchError::Decode(e.to_string()))?;
+        let pixels = width as u64 * height as u64;
+        if pixels > self.max_pixels {
+            return Err(FetchError::TooManyPixels(pixels));
+        }
+        Ok(())
+    }
+
+    /// Decodes an image within the pixel limit.
+    pub fn decode(&self, data: &[u8]) -> Result<DynamicImage, FetchError> {
+        self.check_dimensions(data)?;
+        let mut limits = Limits::default();
+        // Eight bytes per pixel covers 16-bit RGBA, the widest format the
+        // image crate decodes frames to
+        limits.max_alloc = Some(self.max_pixels.saturating_mul(8));
+        let mut reader = Reader::new(Cursor::new(data))
+            .with_guessed_format()
+            .map_err(|e| FetchError::Decode(e.to_string()))?;
+        reader.limits(limits);
+        reader
+            .decode()
+            .map_err(|e| FetchError::Decode(e.to_string()))
+    }
+}
+
+fn check_url(url: &Url, allowed_hosts: &[String]) -> Result<(), FetchError> {
+    if url.scheme() != "https" && url.scheme() != "http" {
+        return Err(FetchError::InvalidUrl(url.to_string()));
+    }
+    let host = match url.host_str() {
+        Some(host) => host.to_lowercase(),
+        None => return Err(FetchError::InvalidUrl(url.to_string())),
+    };
+    // Subdomain entries only match names, `.0.0.1` does not allow `127.0.0.1`
+    let is_domain = url.domain().is_some();
+    let allowed = allowed_hosts
+        .iter()
+        .any(|allowed| match allowed.strip_prefix('.') {
+            Some(domain) => is_domain && (host == domain || host.ends_with(allowed.as_str())),
+            None => host == *allowed,
+        });
+    if allowed {
+        Ok(())
+    } else {
+        Err(FetchError::HostNotAllowed(host))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn check(url: &str, allowed_hosts: &[&str]) -> Result<(), FetchError> {
+        let allowed_hosts: Vec<String> =
+            allowed_hosts.iter().map(|host| host.to_string()).collect();
+        check_url(&Url::parse(url).unwrap(), &allowed_hosts)
+    }
+
+    #[test]
+    fn allows_exact_hosts() {
+        assert!(check("https://example.com/frame.png", &["example.com"]).is_ok());
+        assert!(check("https://EXAMPLE.com/frame.png", &["example.com"]).is_ok());
+        assert!(check("https://a.example.com/frame.png", &["example.com"]).is_err());
+        assert!(check("https://example.org/frame.png", &["example.com"]).is_err());
+    }
+
+    #[test]
+    fn allows_subdomains_of_dotted_entries() {
+        let allowed = [".example.com"];
+        assert!(check("https://a.example.com/frame.png", &allowed).is_ok());
+        assert!(check("https://b.a.example.com/frame.png", &allowed).is_ok());
+        assert!(check("https://example.com/frame.png", &allowed).is_ok());
+        assert!(matches!(
+            check("https://example.com.evil.net/frame.png", &allowed),
+            Err(FetchError::HostNotAllowed(_))
+        ));
+        assert!(matches!(
+            check("https://badexample.com/frame.png", &allowed),
+            Err(FetchError::HostNotAllowed(_))
+        ));
+    }
+
+    #[test]
+    fn allows_http_and_https_only() {
+        assert!(check("http://example.com/frame.png", &["example.com"]).is_ok());
+        assert!(check("https://example.com/frame.png", &["example.com"]).is_ok());
+        assert!(matches!(
+            check("ftp://example.com/frame.png", &["example.com"]),
+            Err(FetchError::InvalidUrl(_))
+        ));
+        assert!(matches!(
+            check("file:///etc/passwd", &["example.com"]),
+            Err(FetchError::InvalidUrl(_))
+        ));
+    }
+
+    #[test]
+    fn matches_ip_literals_exactly() {
+        assert!(check("http://127.0.0.1:8080/frame.png", &["127.0.0.1"]).is_ok());
+        assert!(check("http://[::1]/frame.png", &["[::1]"]).is_ok());
+        assert!(check("http://127.0.0.1/frame.png", &[".0.0.1"]).is_err());
+        assert!(check("http://10.0.0.1/frame.png", &["127.0.0.1"]).is_err());
+        assert!(check("http://169.254.169.254/latest/meta-data", &[".example.com"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn does_not_follow_redirects_to_other_hosts() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 1024];
+            let _ = socket.read(&mut request).await;
+            let response = "HTTP/1.1 302 Found\r\nLocation: http://evil.invalid/frame.png\r\nContent-Length: 0\r\nConnection: close\r\n\r\n";
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+
+        let fetcher = Fetcher::new(&FetchConfig {
+            allowed_hosts: vec!["127.0.0.1".to_string()],
+            connect_timeout_secs: 5,
+            read_timeout_secs: 5,
+            total_timeout_secs: 10,
+            max_bytes: 1024,
+            max_pixels: 1024,
+        });
+        let result = fetcher
+            .download(&format!("http://127.0.0.1:{}/frame.png", port))
+            .await;
+        // The redirect is handed back instead of followed
+        assert!(matches!(result, Err(FetchError::Status(302))));
+    }
+}
@@ -0,0 +1,443 @@
+use crate::config::FrameCacheConfig;
+use crate::functionality::fetch::fetcher;
+use crate::handle_errors::FetchError;
+use crate::metrics;
+use crate::types::frame::BaseFrame;
+use reqwest::header::{HeaderMap, HeaderValue, IF_MODIFIED_SINCE, IF_NONE_MATCH};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static FRAME_CACHE: OnceLock<FrameCache> = OnceLock::new();
+
+/// Sets up the process-wide frame cache. Until it is set up, and in tools that
+/// never do, frames are downloaded every time.
+pub fn init(config: &FrameCacheConfig) -> std::io::Result<()> {
+    let cache = FrameCache::open(config)?;
+    let _ = FRAME_CACHE.set(cache);
+    Ok(())
+}
+
+/// Downloads a base frame, through the frame cache when there is one. Only
+/// base set frames go through the cache; anything named by a request, such as
+/// textures and decals, is downloaded with `fetcher().download`.
+pub async fn fetch(frame: &BaseFrame) -> Result<Vec<u8>, FetchError> {
+    match FRAME_CACHE.get() {
+        Some(cache) => cache.fetch(frame).await,
+        None => fetcher().download(&frame.url).await,
+    }
+}
+
+/// Base frames on disk, keyed by their reference in `image.url`, never by the
+/// URL they are downloaded from: a signed URL without its signature would name
+/// a private object anyone could ask for. A cached frame is used as is while it
+/// is fresh, then revalidated with its `ETag` or `Last-Modified`; the least
+/// recently used frames are evicted once the cache outgrows its size limit.
+pub struct FrameCache {
+    dir: PathBuf,
+    max_bytes: u64,
+    fresh_for_secs: u64,
+    index: Mutex<Index>,
+}
+
+/// What is kept next to each cached frame, as `{hash}.json`.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct EntryMeta {
+    key: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// When the origin last confirmed the frame, in seconds since the epoch.
+    checked_at: u64,
+    size: u64,
+}
+
+struct Entry {
+    meta: EntryMeta,
+    /// Order of last use, higher is more recent.
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct Index {
+    entries: HashMap<String, Entry>,
+    total_bytes: u64,
+    clock: u64,
+}
+
+enum Download {
+    Fetched {
+        data: Vec<u8>,
+        etag: Option<String>,
+        last_modified: Option<String>,
+    },
+    NotModified,
+}
+
+impl FrameCache {
+    fn open(config: &FrameCacheConfig) -> std::io::Result<FrameCache> {
+        let dir = PathBuf::from(&config.dir);
+        std::fs::create_dir_all(&dir)?;
+
+        // Entries from earlier runs count as used in the order they were checked
+        let mut metas = Vec::new();
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("json") => {}
+                // Left behind by a store that never finished
+                Some("partial") => {
+                    let _ = std::fs::remove_file(&path);
+                    continue;
+                }
+                _ => continue,
+            }
+            let meta = std::fs::read(&path)
+                .ok()
+                .and_then(|data| serde_json::from_slice::<EntryMeta>(&data).ok());
+            let hash = path.file_stem().and_then(|stem| stem.to_str());
+            match (meta, hash) {
+                (Some(meta), Some(hash)) if dir.join(format!("{}.bin", hash)).exists() => {
+                    metas.push((hash.to_string(), meta))
+                }
+                _ => {
+                    let _ = std::fs::remove_file(&path);
+                }
+            }
+        }
+        metas.sort_by_key(|(_, meta)| meta.checked_at);
+
+        let mut index = Index::default();
+        for (hash, meta) in metas {
+            index.clock += 1;
+            index.total_bytes += meta.size;
+            let last_used = index.clock;
+            index.entries.insert(hash, Entry { meta, last_used });
+        }
+        metrics::FRAME_CACHE_BYTES.store(index.total_bytes, Ordering::Relaxed);
+
+        let cache = FrameCache {
+            dir,
+            max_bytes: config.max_bytes,
+            fresh_for_secs: config.fresh_for_secs,
+            index: Mutex::new(index),
+        };
+        cache.evict();
+        Ok(cache)
+    }
+
+    pub async fn fetch(&self, frame: &BaseFrame) -> Result<Vec<u8>, FetchError> {
+        let url = frame.url.as_str();
+        // Cached frames are only served for URLs that may still be fetched
+        fetcher().check(url)?;
+        let key = frame.reference.clone();
+        let hash = hash_key(&key);
+        let cached = self.lookup(&hash, &key);
+
+        if let Some(meta) = &cached {
+            if now() < meta.checked_at + self.fresh_for_secs {
+                if let Some(data) = self.read(&hash).await {
+                    metrics::increment(&metrics::FRAME_CACHE_HITS);
+                    return Ok(data);
+                }
+            }
+        }
+
+        let download = match download(url, cached.as_ref()).await {
+            Ok(download) => download,
+            Err(e) => {
+                // An unreachable origin should not stop renders of cached frames
+                let transient = matches!(e, FetchError::Timeout | FetchError::Request(_));
+                if cached.is_some() && transient {
+                    if let Some(data) = self.read(&hash).await {
+                        eprintln!("Error revalidating {}, using the cached frame", key);
+                        return Ok(data);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        match download {
+            Download::NotModified => {
+                let data = match self.read(&hash).await {
+                    Some(data) => data,
+                    None => return self.refetch(url, &key, &hash).await,
+                };
+                metrics::increment(&metrics::FRAME_CACHE_REVALIDATIONS);
+                if let Some(mut meta) = cached {
+                    meta.checked_at = now();
+                    self.write_meta(&hash, &meta).await;
+                    self.update(&hash, meta);
+                }
+                Ok(data)
+            }
+            Download::Fetched {
+                data,
+                etag,
+                last_modified,
+            } => {
+                metrics::increment(&metrics::FRAME_CACHE_MISSES);
+                self.store(&hash, &key, &data, etag, last_modified).await;
+                Ok(data)
+            }
+        }
+    }
+
+    /// Unconditional download for when a cached frame vanished from disk
+    /// after the origin already confirmed it.
+    async fn refetch(&self, url: &str, key: &str, hash: &str) -> Result<Vec<u8>, FetchError> {
+        self.forget(hash);
+        match download(url, None).await? {
+            Download::Fetched {
+                data,
+                etag,
+                last_modified,
+            } => {
+                metrics::increment(&metrics::FRAME_CACHE_MISSES);
+                self.store(hash, key, &data, etag, last_modified).await;
+                Ok(data)
+            }
+            Download::NotModified => Err(FetchError::Status(304)),
+        }
+    }
+
+    /// Metadata of a cached frame, marking it as just used.
+    fn lookup(&self, hash: &str, key: &str) -> Option<EntryMeta> {
+        let mut index = self.index.lock().unwrap();
+        index.clock += 1;
+        let clock = index.clock;
+        let entry = index.entries.get_mut(hash)?;
+        // Different URLs with the same hash are simply not cached together
+        if entry.meta.key != key {
+            return None;
+        }
+        entry.last_used = clock;
+        Some(entry.meta.clone())
+    }
+
+    async fn read(&self, hash: &str) -> Option<Vec<u8>> {
+        match tokio::fs::read(self.dir.join(format!("{}.bin", hash))).await {
+            Ok(data) => Some(data),
+            Err(_) => {
+                self.forget(hash);
+                None
+            }
+        }
+    }
+
+    async fn store(
+        &self,
+        hash: &str,
+        key: &str,
+        data: &[u8],
+        etag: Option<String>,
+        last_modified: Option<String>,
+    ) {
+        let size = data.len() as u64;
+        if size > self.max_bytes {
+            return;
+        }
+        if let Err(e) = self.write_file(&format!("{}.bin", hash), data).await {
+            eprintln!("Error caching frame {} {}", key, e);
+            return;
+        }
+
+        let meta = EntryMeta {
+            key: key.to_string(),
+            etag,
+            last_modified,
+            checked_at: now(),
+            size,
+        };
+        self.write_meta(hash, &meta).await;
+        self.update(hash, meta);
+        self.evict();
+    }
+
+    async fn write_meta(&self, hash: &str, meta: &EntryMeta) {
+        let written = match serde_json::to_vec(meta) {
+            Ok(data) => self.write_file(&format!("{}.json", hash), &data).await,
+            Err(e) => Err(std::io::Error::new(std::io::ErrorKind::Other, e)),
+        };
+        if let Err(e) = written {
+            eprintln!("Error caching frame {} {}", meta.key, e);
+        }
+    }
+
+    /// Writes a file under a temporary name of its own first, so a crash never
+    /// leaves a truncated file behind under the real name and stores of the
+    /// same frame running at the same time never write into each other's file.
+    async fn write_file(&self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        let path = self.dir.join(name);
+        let partial = self
+            .dir
+            .join(format!("{}.{}.partial", name, uuid::Uuid::new_v4()));
+        let written = match tokio::fs::write(&partial, data).await {
+            Ok(()) => tokio::fs::rename(&partial, &path).await,
+            Err(e) => Err(e),
+        };
+        if written.is_err() {
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+        written
+    }
+
+    fn update(&self, hash: &str, meta: EntryMeta) {
+        let mut index = self.index.lock().unwrap();
+        index.clock += 1;
+        let last_used = index.clock;
+        let size = meta.size;
+        if let Some(old) = index
+            .entries
+            .insert(hash.to_string(), Entry { meta, last_used })
+        {
+            index.total_bytes -= old.meta.size;
+        }
+        index.total_bytes += size;
+        metrics::FRAME_CACHE_BYTES.store(index.total_bytes, Ordering::Relaxed);
+    }
+
+    fn forget(&self, hash: &str) {
+        let mut index = self.index.lock().unwrap();
+        if let Some(old) = index.entries.remove(hash) {
+            index.total_bytes -= old.meta.size;
+        }
+        metrics::FRAME_CACHE_BYTES.store(index.total_bytes, Ordering::Relaxed);
+    }
+
+    /// Removes least recently used frames until the cache fits its limit.
+    fn evict(&self) {
+        let mut evicted = Vec::new();
+        {
+            let mut index = self.index.lock().unwrap();
+            while index.total_bytes > self.max_bytes {
+                let oldest = index
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.last_used)
+                    .map(|(hash, _)| hash.clone());
+                let Some(hash) = oldest else {
+                    break;
+                };
+                if let Some(entry) = index.entries.remove(&hash) {
+                    index.total_bytes -= entry.meta.size;
+                }
+                evicted.push(hash);
+            }
+            metrics::FRAME_CACHE_BYTES.store(index.total_bytes, Ordering::Relaxed);
+        }
+        for hash in evicted {
+            metrics::increment(&metrics::FRAME_CACHE_EVICTIONS);
+            let _ = std::fs::remove_file(self.dir.join(format!("{}.bin", hash)));
+            let _ = std::fs::remove_file(self.dir.join(format!("{}.json", hash)));
+        }
+    }
+}
+
+/// GET through the shared fetcher, conditional on `cached` when given.
+async fn download(url: &str, cached: Option<&EntryMeta>) -> Result<Download, FetchError> {
+    let mut headers = HeaderMap::new();
+    if let Some(meta) = cached {
+        let validators = [
+            (IF_NONE_MATCH, &meta.etag),
+            (IF_MODIFIED_SINCE, &meta.last_modified),
+        ];
+        for (name, value) in validators {
+            if let Some(value) = value.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+                headers.insert(name, value);
+            }
+        }
+    }
+    match fetcher().get(url, headers).await? {
+        Some(fetched) => Ok(Download::Fetched {
+            data: fetched.data,
+            etag: fetched.etag,
+            last_modified: fetched.last_modified,
+        }),
+        None if cached.is_some() => Ok(Download::NotModified),
+        // Without validators sent, a 304 is not an answer
+        None => Err(FetchError::Status(304)),
+    }
+}
+
+/// File name of a cached frame. The hash has to stay the same across builds,
+/// since the cache outlives the process.
+fn hash_key(key: &str) -> String {
+    format!("{:x}", Sha256::digest(key.as_bytes()))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_in_temp_dir() -> FrameCache {
+        let dir =
+            std::env::temp_dir().join(format!("carcaro-frame-cache-{}", uuid::Uuid::new_v4()));
+        FrameCache::open(&FrameCacheConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            max_bytes: 1 << 20,
+            fresh_for_secs: 60,
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn concurrent_stores_of_a_frame_keep_one_whole_copy() {
+        let cache = open_in_temp_dir();
+        let key = "base/frame_0.png";
+        let hash = hash_key(key);
+        let first = vec![1u8; 64 * 1024];
+        let second = vec![2u8; 32 * 1024];
+
+        tokio::join!(
+            cache.store(&hash, key, &first, Some("\"1\"".to_string()), None),
+            cache.store(&hash, key, &second, Some("\"2\"".to_string()), None),
+        );
+
+        let data = cache.read(&hash).await.unwrap();
+        assert!(data == first || data == second);
+        let meta = cache.lookup(&hash, key).unwrap();
+        assert!(meta.size == first.len() as u64 || meta.size == second.len() as u64);
+        assert_eq!(cache.index.lock().unwrap().total_bytes, meta.size);
+
+        let names: Vec<String> = std::fs::read_dir(&cache.dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        assert!(
+            names.iter().all(|name| !name.ends_with(".partial")),
+            "{:?}",
+            names
+        );
+        assert_eq!(names.len(), 2);
+
+        std::fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn open_removes_unfinished_stores() {
+        let cache = open_in_temp_dir();
+        let partial = cache.dir.join("0123.bin.abcd.partial");
+        std::fs::write(&partial, b"truncated").unwrap();
+
+        let reopened = FrameCache::open(&FrameCacheConfig {
+            dir: cache.dir.to_string_lossy().into_owned(),
+            max_bytes: 1 << 20,
+            fresh_for_secs: 60,
+        })
+        .unwrap();
+        assert!(!partial.exists());
+
+        std::fs::remove_dir_all(&reopened.dir).unwrap();
+    }
+}
@@ -0,0 +1,167 @@
+use crate::handle_errors::StorageError;
+use crate::storage::{is_key, ObjectInfo, Storage};
+use crate::types::frame::RangeRequest;
+use warp::http::{header, Response, StatusCode};
+use warp::hyper::Body;
+
+/// A render never changes under its key; a new render gets a new image id.
+const RENDER_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+/// Base sets can be replaced in place, so caches revalidate them every time.
+const BASE_CACHE_CONTROL: &str = "public, no-cache";
+/// Reads of a frame that changed while it was being served.
+const FRAME_ATTEMPTS: u32 = 3;
+
+/// Headers of a frame request that decide what is sent back.
+#[derive(Debug, Default)]
+pub struct FrameConditions {
+    pub if_none_match: Option<String>,
+    pub range: Option<String>,
+    pub if_range: Option<String>,
+}
+
+/// Answers a frame request from storage: the frame, a part of it, or
+/// `304 Not Modified` when the client's copy is current. Frames hosted
+/// elsewhere are redirected to.
+pub async fn serve_frame(
+    storage: &Storage,
+    reference: &str,
+    render: bool,
+    conditions: FrameConditions,
+) -> Result<Response<Body>, StorageError> {
+    if !is_key(reference) {
+        return Ok(Response::builder()
+            .status(StatusCode::FOUND)
+            .header(header::LOCATION, reference)
+            .body(Body::empty())
+            .unwrap());
+    }
+
+    // The object can be replaced between reading its headers and its body, so
+    // the body is only read while it is still the version the headers describe
+    let mut attempt = 0;
+    loop {
+        let info = storage.head(reference).await?;
+        match respond(storage, reference, render, &conditions, info).await {
+            Err(e) if e.is_precondition_failed() && attempt + 1 < FRAME_ATTEMPTS => attempt += 1,
+            result => return result,
+        }
+    }
+}
+
+async fn respond(
+    storage: &Storage,
+    reference: &str,
+    render: bool,
+    conditions: &FrameConditions,
+    info: ObjectInfo,
+) -> Result<Response<Body>, StorageError> {
+    let etag = info.etag.as_deref().map(quoted);
+    let cache_control = if render {
+        RENDER_CACHE_CONTROL
+    } else {
+        BASE_CACHE_CONTROL
+    };
+
+    let mut response = Response::builder()
+        .header(header::CACHE_CONTROL, cache_control)
+        .header(header::ACCEPT_RANGES, "bytes");
+    if let Some(etag) = &etag {
+        response = response.header(header::ETAG, etag);
+    }
+
+    if let (Some(etag), Some(if_none_match)) = (&etag, &conditions.if_none_match) {
+        if matches_etag(if_none_match, etag) {
+            return Ok(response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .unwrap());
+        }
+    }
+
+    // A range only applies to the version the client already has part of
+    let range_header = match (&conditions.if_range, &etag) {
+        (Some(if_range), Some(etag)) if if_range.trim() != etag => None,
+        (Some(_), None) => None,
+        _ => conditions.range.as_deref(),
+    };
+    let range = match RangeRequest::parse(range_header, info.size) {
+        RangeRequest::Full => None,
+        RangeRequest::Partial(range) => Some(range),
+        RangeRequest::Unsatisfiable => {
+            return Ok(response
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header(header::CONTENT_RANGE, format!("bytes */{}", info.size))
+                .body(Body::empty())
+                .unwrap());
+        }
+    };
+
+    let content_type = info
+        .content_type
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let stream = storage
+        .stream(reference, range, info.etag.as_deref())
+        .await?;
+    let body = Body::wrap_stream(stream);
+    let response = response.header(header::CONTENT_TYPE, content_type);
+    let response = match range {
+        Some(range) => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", range.start, range.end, info.size),
+            )
+            .header(header::CONTENT_LENGTH, range.len()),
+        None => response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, info.size),
+    };
+    Ok(response.body(body).unwrap())
+}
+
+/// Backends differ in whether they quote entity tags; HTTP wants them quoted.
+fn quoted(etag: &str) -> String {
+    if etag.starts_with('"') || etag.starts_with("W/") {
+        etag.to_string()
+    } else {
+        format!("\"{}\"", etag)
+    }
+}
+
+/// `If-None-Match` matches with `*` or any listed tag, compared weakly.
+fn matches_etag(if_none_match: &str, etag: &str) -> bool {
+    let etag = etag.trim_start_matches("W/");
+    if_none_match
+        .split(',')
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_bare_etags_only() {
+        assert_eq!(quoted("0x8D"), "\"0x8D\"");
+        assert_eq!(quoted("\"0x8D\""), "\"0x8D\"");
+        assert_eq!(quoted("W/\"0x8D\""), "W/\"0x8D\"");
+    }
+
+    #[test]
+    fn matches_listed_tags_and_wildcard() {
+        assert!(matches_etag("\"a\"", "\"a\""));
+        assert!(matches_etag("\"b\", \"a\"", "\"a\""));
+        assert!(matches_etag("*", "\"a\""));
+        assert!(!matches_etag("\"b\"", "\"a\""));
+        assert!(!matches_etag("", "\"a\""));
+    }
+
+    #[test]
+    fn compares_weak_tags_weakly() {
+        assert!(matches_etag("W/\"a\"", "\"a\""));
+        assert!(matches_etag("\"a\"", "W/\"a\""));
+        assert!(matches_etag("\"b\", W/\"a\"", "W/\"a\""));
+        assert!(!matches_etag("W/\"b\"", "W/\"a\""));
+    }
+}
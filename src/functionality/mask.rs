@@ -0,0 +1,158 @@
+use opencv::core::{Mat, MatTrait, MatTraitConst, Point, Scalar, Size, Vec4i, Vector};
+use opencv::imgproc;
+
+/// Components smaller than this fraction of the largest one are treated as
+/// background that happened to match the paint color.
+const MIN_COMPONENT_RATIO: f64 = 0.1;
+/// Holes smaller than this fraction of the frame get filled; bigger ones are
+/// windows, wheels and the like and stay unpainted.
+const MAX_HOLE_RATIO: f64 = 0.002;
+const SPECKLE_KERNEL_SIZE: i32 = 5;
+const FEATHER_KERNEL_SIZE: i32 = 7;
+
+/// Ranges of base paint colors a frame can be masked with. `Standard` is what
+/// the studio base cars are shot in; `Wide` also picks up paint in deep shade
+/// or strong reflections, `Strict` leaves out pale and dark spots.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Default)]
+pub enum MaskProfile {
+    #[default]
+    Standard,
+    Wide,
+    Strict,
+}
+
+impl MaskProfile {
+    /// Lower and upper HSV bounds, with OpenCV's 0 to 180 hue scale.
+    pub fn bounds(&self) -> (Scalar, Scalar) {
+        match self {
+            MaskProfile::Standard => (
+                Scalar::new(10.0, 1.0, 10.0, 0.0),
+                Scalar::new(45.0, 255.0, 255.0, 255.0),
+            ),
+            MaskProfile::Wide => (
+                Scalar::new(5.0, 1.0, 5.0, 0.0),
+                Scalar::new(55.0, 255.0, 255.0, 255.0),
+            ),
+            MaskProfile::Strict => (
+                Scalar::new(12.0, 60.0, 40.0, 0.0),
+                Scalar::new(40.0, 255.0, 255.0, 255.0),
+            ),
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<MaskProfile> {
+        match name {
+            "standard" => Some(MaskProfile::Standard),
+            "wide" => Some(MaskProfile::Wide),
+            "strict" => Some(MaskProfile::Strict),
+            _ => None,
+        }
+    }
+}
+
+/// Turns the binary color-range mask into a soft alpha mask: speckles are
+/// removed, only the largest connected components are kept, small holes are
+/// filled and the edges are feathered.
+pub fn refine_mask(mask: &Mat) -> Result<Mat, opencv::Error> {
+    let kernel = imgproc::get_structuring_element(
+        imgproc::MORPH_ELLIPSE,
+        Size::new(SPECKLE_KERNEL_SIZE, SPECKLE_KERNEL_SIZE),
+        Point::new(-1, -1),
+    )?;
+    let mut opened = Mat::default();
+    imgproc::morphology_ex(
+        mask,
+        &mut opened,
+        imgproc::MORPH_OPEN,
+        &kernel,
+        Point::new(-1, -1),
+        1,
+        opencv::core::BORDER_CONSTANT,
+        imgproc::morphology_default_border_value()?,
+    )?;
+
+    let mut kept = keep_largest_components(&opened)?;
+    fill_small_holes(&mut kept)?;
+
+    let mut feathered = Mat::default();
+    imgproc::gaussian_blur(
+        &kept,
+        &mut feathered,
+        Size::new(FEATHER_KERNEL_SIZE, FEATHER_KERNEL_SIZE),
+        0.0,
+        0.0,
+        opencv::core::BORDER_DEFAULT,
+    )?;
+    Ok(feathered)
+}
+
+fn keep_largest_components(mask: &Mat) -> Result<Mat, opencv::Error> {
+    let mut labels = Mat::default();
+    let mut stats = Mat::default();
+    let mut centroids = Mat::default();
+    let count = imgproc::connected_components_with_stats(
+        mask,
+        &mut labels,
+        &mut stats,
+        &mut centroids,
+        8,
+        opencv::core::CV_32S,
+    )?;
+
+    // Label 0 is the background
+    let mut areas = vec![0; count as usize];
+    for label in 1..count {
+        areas[label as usize] = *stats.at_2d::<i32>(label, imgproc::CC_STAT_AREA)?;
+    }
+    let largest = areas.iter().copied().max().unwrap_or(0);
+    let keep: Vec<bool> = areas
+        .iter()
+        .enumerate()
+        .map(|(label, &area)| {
+            label != 0 && largest > 0 && area as f64 >= largest as f64 * MIN_COMPONENT_RATIO
+        })
+        .collect();
+
+    let mut kept = Mat::zeros(mask.rows(), mask.cols(), opencv::core::CV_8U)?.to_mat()?;
+    let label_data = labels.data_typed::<i32>()?;
+    let kept_data = kept.data_bytes_mut()?;
+    for (value, &label) in kept_data.iter_mut().zip(label_data) {
+        if keep[label as usize] {
+            *value = 255;
+        }
+    }
+    Ok(kept)
+}
+
+fn fill_small_holes(mask: &mut Mat) -> Result<(), opencv::Error> {
+    let mut contours = Vector::<Vector<Point>>::new();
+    let mut hierarchy = Vector::<Vec4i>::new();
+    imgproc::find_contours_with_hierarchy(
+        mask,
+        &mut contours,
+        &mut hierarchy,
+        imgproc::RETR_CCOMP,
+        imgproc::CHAIN_APPROX_SIMPLE,
+        Point::new(0, 0),
+    )?;
+
+    let max_hole_area = (mask.rows() * mask.cols()) as f64 * MAX_HOLE_RATIO;
+    for (i, contour) in contours.iter().enumerate() {
+        // With RETR_CCOMP, contours that have a parent are the holes of an outer contour
+        let is_hole = hierarchy.get(i)?[3] >= 0;
+        if is_hole && imgproc::contour_area(&contour, false)? <= max_hole_area {
+            imgproc::draw_contours(
+                mask,
+                &contours,
+                i as i32,
+                Scalar::all(255.0),
+                imgproc::FILLED,
+                imgproc::LINE_8,
+                &opencv::core::no_array(),
+                0,
+                Point::new(0, 0),
+            )?;
+        }
+    }
+    Ok(())
+}
@@ -1,2 +1,15 @@
+pub mod accuracy;
+pub mod animation;
 pub mod color_swap;
+pub mod color_wall;
+pub mod comparison;
 pub mod container_generation;
+pub mod encoding;
+pub mod fetch;
+pub mod frame_cache;
+pub mod frames;
+pub mod mask;
+pub mod preflight;
+pub mod preview;
+pub mod recolor;
+pub mod reconcile;
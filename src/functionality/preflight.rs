@@ -0,0 +1,264 @@
+use crate::functionality::accuracy::to_lab;
+use crate::functionality::color_swap::{desired_areas, download_base_frame};
+use crate::functionality::recolor::{
+    continuous_data, hsl_to_rgb, non_linear_transform, pixel_lightness, PaintTarget,
+};
+use crate::handle_errors::Error;
+use crate::types::accuracy::POOR_MEAN_DELTA_E;
+use crate::types::frame::BaseFrame;
+use crate::types::preflight::{ColorCheck, PreflightReport, PreflightWarning};
+use opencv::core::{Mat, MatTraitConst};
+use opencv::imgcodecs;
+use palette::color_difference::Ciede2000;
+
+/// Base lightness is bucketed by whole percent, which is finer than the
+/// prediction needs to be.
+const LIGHTNESS_BINS: usize = 101;
+
+/// How far in percent the predicted lightness may miss the target before warning.
+const LIGHTNESS_TOLERANCE: f64 = 5.0;
+
+const MIN_MASK_VALUE: u8 = 128;
+
+/// Histogram of the HSL lightness of the paint in a set of base frames.
+/// Everything `apply_color_change` does to a pixel depends only on that
+/// lightness and the target, so this is all a prediction needs.
+struct LightnessHistogram {
+    counts: [u64; LIGHTNESS_BINS],
+    total: u64,
+}
+
+impl LightnessHistogram {
+    fn new() -> Self {
+        LightnessHistogram {
+            counts: [0; LIGHTNESS_BINS],
+            total: 0,
+        }
+    }
+
+    fn add_frame(&mut self, frame: &Mat, mask: &Mat) -> Result<(), opencv::Error> {
+        let width = frame.cols() as usize;
+        if width == 0 {
+            return Ok(());
+        }
+        let (mut frame_copy, mut mask_copy) = (Mat::default(), Mat::default());
+        let frame_data = continuous_data(frame, &mut frame_copy)?;
+        let mask_data = continuous_data(mask, &mut mask_copy)?;
+
+        for (bgr_pixel, &mask_value) in frame_data.chunks_exact(3).zip(mask_data) {
+            if mask_value < MIN_MASK_VALUE {
+                continue;
+            }
+            let lightness = pixel_lightness(bgr_pixel[2], bgr_pixel[1], bgr_pixel[0]);
+            self.counts[lightness.round() as usize] += 1;
+            self.total += 1;
+        }
+        Ok(())
+    }
+
+    /// Weighted mean of `f(base_lightness)` over the painted pixels.
+    fn mean<F: Fn(f64) -> f64>(&self, f: F) -> f64 {
+        let sum: f64 = self
+            .counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(lightness, &count)| f(lightness as f64) * count as f64)
+            .sum();
+        sum / self.total as f64
+    }
+
+    /// Mean lightness the paint ends up with when `target_lightness` is requested.
+    fn predicted_lightness(&self, target_lightness: f64) -> f64 {
+        self.mean(|base| rendered_lightness(base, target_lightness))
+    }
+}
+
+/// Same lightness mapping as `apply_color_change`.
+fn rendered_lightness(base_lightness: f64, target_lightness: f64) -> f64 {
+    (base_lightness + non_linear_transform(target_lightness - base_lightness)).clamp(0.0, 100.0)
+}
+
+/// Estimates, before rendering, how well each of `targets` can be reproduced
+/// on the given base frames.
+pub async fn preflight(
+    base_frames: Vec<BaseFrame>,
+    targets: Vec<[u8; 3]>,
+) -> Result<PreflightReport, Error> {
+    let mut frames = Vec::new();
+    for base_frame in &base_frames {
+        frames.push(download_base_frame(base_frame, imgcodecs::IMREAD_COLOR).await?);
+    }
+    // Masking is CPU-bound, so it runs off the async workers with the prediction
+    tokio::task::spawn_blocking(move || {
+        let mut histogram = LightnessHistogram::new();
+        for frame in &frames {
+            let mask = desired_areas(frame).map_err(|_| Error::ColorSwapError)?;
+            histogram
+                .add_frame(frame, &mask)
+                .map_err(|_| Error::ColorSwapError)?;
+        }
+        predict(&histogram, targets)
+    })
+    .await
+    .map_err(|_| Error::ColorSwapError)?
+}
+
+fn predict(
+    histogram: &LightnessHistogram,
+    targets: Vec<[u8; 3]>,
+) -> Result<PreflightReport, Error> {
+    if histogram.total == 0 {
+        return Err(Error::NoPaintArea);
+    }
+
+    let reachable_lightness = [
+        histogram.predicted_lightness(0.0),
+        histogram.predicted_lightness(100.0),
+    ];
+    let colors = targets
+        .into_iter()
+        .map(|color| check_color(histogram, color))
+        .collect();
+
+    Ok(PreflightReport {
+        reachable_lightness,
+        colors,
+    })
+}
+
+fn check_color(histogram: &LightnessHistogram, color: [u8; 3]) -> ColorCheck {
+    let target = PaintTarget::from(color);
+    let target_lab = to_lab(color);
+    let predicted_lightness = histogram.predicted_lightness(target.lightness);
+    let predicted_delta_e = histogram.mean(|base| {
+        let rendered = rendered_lightness(base, target.lightness);
+        let rendered_lab = to_lab(hsl_to_rgb(target.hue, target.saturation, rendered));
+        rendered_lab.difference(target_lab) as f64
+    });
+
+    let mut warnings = Vec::new();
+    if predicted_lightness < target.lightness - LIGHTNESS_TOLERANCE {
+        warnings.push(PreflightWarning::TooLight);
+    }
+    if predicted_lightness > target.lightness + LIGHTNESS_TOLERANCE {
+        warnings.push(PreflightWarning::TooDark);
+    }
+    if predicted_delta_e > POOR_MEAN_DELTA_E {
+        warnings.push(PreflightWarning::PoorMatch);
+    }
+
+    // The lightness to ask for so the render lands as close as possible to the
+    // requested one, clamped by what the base frames can reach
+    let suggested = if warnings.is_empty() {
+        None
+    } else {
+        let requested = (0..=100)
+            .map(|lightness| lightness as f64)
+            .min_by(|a, b| {
+                let miss_a = (histogram.predicted_lightness(*a) - target.lightness).abs();
+                let miss_b = (histogram.predicted_lightness(*b) - target.lightness).abs();
+                miss_a.total_cmp(&miss_b)
+            })
+            .unwrap_or(target.lightness);
+        Some(hsl_to_rgb(target.hue, target.saturation, requested))
+            .filter(|suggested| *suggested != color)
+    };
+
+    ColorCheck {
+        color,
+        target_lightness: target.lightness,
+        predicted_lightness,
+        predicted_delta_e,
+        warnings,
+        suggested,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opencv::core::{MatTrait, Scalar, Vec3b, CV_8UC1, CV_8UC3};
+
+    /// A 10x10 gray frame, lightness 20% in the top half and 60% in the bottom
+    /// half, with the first column left out of the mask.
+    fn two_tone_frame() -> (Mat, Mat) {
+        let mut frame = Mat::new_rows_cols_with_default(10, 10, CV_8UC3, Scalar::all(0.0)).unwrap();
+        let mut mask =
+            Mat::new_rows_cols_with_default(10, 10, CV_8UC1, Scalar::all(255.0)).unwrap();
+        for y in 0..10 {
+            let gray = if y < 5 { 51 } else { 153 };
+            for x in 0..10 {
+                *frame.at_2d_mut::<Vec3b>(y, x).unwrap() = Vec3b::from([gray, gray, gray]);
+            }
+            *mask.at_2d_mut::<u8>(y, 0).unwrap() = MIN_MASK_VALUE - 1;
+        }
+        (frame, mask)
+    }
+
+    fn histogram() -> LightnessHistogram {
+        let (frame, mask) = two_tone_frame();
+        let mut histogram = LightnessHistogram::new();
+        histogram.add_frame(&frame, &mask).unwrap();
+        histogram
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn counts_masked_lightness() {
+        let histogram = histogram();
+        assert_eq!(histogram.total, 90);
+        assert_eq!(histogram.counts[20], 45);
+        assert_eq!(histogram.counts[60], 45);
+        assert_eq!(histogram.counts.iter().sum::<u64>(), 90);
+    }
+
+    #[test]
+    fn predicts_the_mean_rendered_lightness() {
+        let histogram = histogram();
+        for target in [0.0, 25.0, 40.0, 75.0, 100.0] {
+            let expected =
+                (rendered_lightness(20.0, target) + rendered_lightness(60.0, target)) / 2.0;
+            assert!(close(histogram.predicted_lightness(target), expected));
+        }
+        // Halfway between both tones they are pushed apart by the same amount
+        assert!(close(histogram.predicted_lightness(40.0), 40.0));
+    }
+
+    #[test]
+    fn reports_the_reachable_range() {
+        let report = predict(
+            &histogram(),
+            vec![[102, 102, 102], [255, 255, 255], [0, 0, 0]],
+        )
+        .unwrap();
+        let darkest = (20.0 - 20f64.powf(0.87) + 60.0 - 60f64.powf(0.87)) / 2.0;
+        let lightest = (20.0 + 80f64.powf(0.87) + 60.0 + 40f64.powf(0.87)) / 2.0;
+        assert!(close(report.reachable_lightness[0], darkest));
+        assert!(close(report.reachable_lightness[1], lightest));
+
+        let [mid, white, black] = &report.colors[..] else {
+            panic!("expected three colors");
+        };
+        assert!(close(mid.target_lightness, 40.0));
+        assert!(!mid.warnings.contains(&PreflightWarning::TooLight));
+        assert!(!mid.warnings.contains(&PreflightWarning::TooDark));
+        assert!(white.warnings.contains(&PreflightWarning::TooLight));
+        assert!(black.warnings.contains(&PreflightWarning::TooDark));
+    }
+
+    #[test]
+    fn rejects_frames_without_paint() {
+        let (frame, _) = two_tone_frame();
+        let mask = Mat::new_rows_cols_with_default(10, 10, CV_8UC1, Scalar::all(0.0)).unwrap();
+        let mut histogram = LightnessHistogram::new();
+        histogram.add_frame(&frame, &mask).unwrap();
+        assert!(matches!(
+            predict(&histogram, vec![[102, 102, 102]]),
+            Err(Error::NoPaintArea)
+        ));
+    }
+}
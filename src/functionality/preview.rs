@@ -0,0 +1,61 @@
+use crate::functionality::color_swap::{
+    desired_areas, download_base_frame, download_decal, download_frame, recolor_frame,
+};
+use crate::handle_errors::Error;
+use crate::types::finish::Finish;
+use crate::types::frame::BaseFrame;
+use crate::types::preview::{PreviewFormat, PreviewRequest};
+use opencv::core::{Mat, MatTraitConst, Size, Vector};
+use opencv::{imgcodecs, imgproc};
+
+const PREVIEW_WEBP_QUALITY: i32 = 80;
+
+/// Recolors one frame at preview size and returns the encoded image.
+/// `base_frame` is the frame the request picked, resolved by the caller. Nothing is written
+/// to disk, storage or the database.
+pub async fn render_preview(
+    request: PreviewRequest,
+    base_frame: BaseFrame,
+) -> Result<Vec<u8>, Error> {
+    request.validate()?;
+    let mut finish = request.finish()?;
+
+    let base_image = download_base_frame(&base_frame, imgcodecs::IMREAD_COLOR).await?;
+    let factor = (request.width as f64 / base_image.cols() as f64).min(1.0);
+    let mut frame = Mat::default();
+    let size = Size::new(
+        (base_image.cols() as f64 * factor).round() as i32,
+        (base_image.rows() as f64 * factor).round() as i32,
+    );
+    imgproc::resize(&base_image, &mut frame, size, 0.0, 0.0, imgproc::INTER_AREA)
+        .map_err(|_| Error::ColorSwapError)?;
+
+    let texture_image = match &mut finish {
+        Finish::Texture(texture) => {
+            // Tiles keep the size they would have on the full frame
+            texture.scale *= factor;
+            Some(download_frame(texture.url.clone(), imgcodecs::IMREAD_COLOR).await?)
+        }
+        _ => None,
+    };
+
+    let mut decals = Vec::new();
+    for decal in request.decals.iter().filter(|decal| decal.frame == request.frame) {
+        let decal_image = download_decal(decal.url.clone()).await?;
+        decals.push((decal.clone(), decal_image));
+    }
+
+    let mask = desired_areas(&frame).map_err(|_| Error::ColorSwapError)?;
+    recolor_frame(&mut frame, &mask, &finish, texture_image.as_ref(), &decals)
+        .map_err(|_| Error::ColorSwapError)?;
+
+    let mut params = Vector::<i32>::new();
+    if request.format == PreviewFormat::Webp {
+        params.push(imgcodecs::IMWRITE_WEBP_QUALITY);
+        params.push(PREVIEW_WEBP_QUALITY);
+    }
+    let mut encoded = Vector::<u8>::new();
+    imgcodecs::imencode(request.format.extension(), &frame, &mut encoded, &params)
+        .map_err(|_| Error::ColorSwapError)?;
+    Ok(encoded.to_vec())
+}
@@ -0,0 +1,337 @@
+use crate::types::decal::DecalPlacement;
+use crate::types::texture::{Texture, TextureMode};
+use opencv::core::{Mat, MatTrait, MatTraitConst, Point2f, Scalar, Size, Vector, CV_16U, CV_8U};
+use opencv::imgproc;
+use rayon::prelude::*;
+
+/// Target paint color in HSL, on the same scales `colorsys` uses: hue in
+/// degrees, saturation and lightness in percent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PaintTarget {
+    pub hue: f64,
+    pub saturation: f64,
+    pub lightness: f64,
+}
+
+impl From<[u8; 3]> for PaintTarget {
+    fn from(rgb: [u8; 3]) -> Self {
+        let r = rgb[0] as f64 / 255.0;
+        let g = rgb[1] as f64 / 255.0;
+        let b = rgb[2] as f64 / 255.0;
+        let max = r.max(g).max(b);
+        let min = r.min(g).min(b);
+        let lightness = (max + min) / 2.0;
+        if max == min {
+            return PaintTarget {
+                hue: 0.0,
+                saturation: 0.0,
+                lightness: lightness * 100.0,
+            };
+        }
+
+        let delta = max - min;
+        let saturation = if lightness > 0.5 {
+            delta / (2.0 - max - min)
+        } else {
+            delta / (max + min)
+        };
+        let hue = if max == r {
+            (g - b) / delta + if g < b { 6.0 } else { 0.0 }
+        } else if max == g {
+            (b - r) / delta + 2.0
+        } else {
+            (r - g) / delta + 4.0
+        };
+
+        PaintTarget {
+            hue: hue * 60.0,
+            saturation: saturation * 100.0,
+            lightness: lightness * 100.0,
+        }
+    }
+}
+
+/// HSL lightness in percent of a single pixel.
+pub(crate) fn pixel_lightness(r: u8, g: u8, b: u8) -> f64 {
+    let max = r.max(g).max(b) as f64;
+    let min = r.min(g).min(b) as f64;
+    (max + min) / 5.1
+}
+
+pub(crate) fn hsl_to_rgb(hue: f64, saturation: f64, lightness: f64) -> [u8; 3] {
+    let s = saturation / 100.0;
+    let l = lightness / 100.0;
+    if s <= 0.0 {
+        let v = (l * 255.0).round() as u8;
+        return [v, v, v];
+    }
+
+    let q = if l < 0.5 {
+        l * (1.0 + s)
+    } else {
+        l + s - l * s
+    };
+    let p = 2.0 * l - q;
+    let h = hue / 360.0;
+    let channel = |t: f64| {
+        let t = t.rem_euclid(1.0);
+        let v = if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        };
+        (v * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+    [channel(h + 1.0 / 3.0), channel(h), channel(h - 1.0 / 3.0)]
+}
+
+fn blend(original: u8, painted: f64, alpha: f64) -> u8 {
+    let painted = painted.clamp(0.0, 255.0);
+    (original as f64 * (1.0 - alpha) + painted * alpha).round() as u8
+}
+
+pub fn non_linear_transform(delta_lightness: f64) -> f64 {
+    delta_lightness.signum() * delta_lightness.abs().powf(0.87)
+}
+
+/// Row slices below rely on the pixel data being one contiguous buffer, which
+/// is what `imread` and every OpenCV operation producing a new `Mat` give us.
+fn make_continuous(mat: &mut Mat) -> Result<(), opencv::Error> {
+    if !mat.is_continuous() {
+        *mat = mat.try_clone()?;
+    }
+    Ok(())
+}
+
+pub(crate) fn continuous_data<'a>(
+    mat: &'a Mat,
+    copy: &'a mut Mat,
+) -> Result<&'a [u8], opencv::Error> {
+    if mat.is_continuous() {
+        return mat.data_bytes();
+    }
+    *copy = mat.try_clone()?;
+    copy.data_bytes()
+}
+
+/// Recolors every masked pixel with the hue and saturation of `target_at(x, y)`,
+/// moving its lightness towards the target while keeping the frame's shading.
+/// The mask is a soft alpha mask, so feathered edges blend with the original.
+/// Rows are processed in parallel.
+pub fn apply_color_change<F>(
+    original_image: &mut Mat,
+    mask: &Mat,
+    target_at: F,
+) -> Result<(), opencv::Error>
+where
+    F: Fn(i32, i32) -> PaintTarget + Sync,
+{
+    let width = original_image.cols() as usize;
+    if width == 0 {
+        return Ok(());
+    }
+    make_continuous(original_image)?;
+    let mut mask_copy = Mat::default();
+    let mask_data = continuous_data(mask, &mut mask_copy)?;
+    let image_data = original_image.data_bytes_mut()?;
+
+    image_data
+        .par_chunks_mut(width * 3)
+        .zip(mask_data.par_chunks(width))
+        .enumerate()
+        .for_each(|(y, (row, mask_row))| {
+            for (x, (bgr_pixel, &mask_value)) in row.chunks_exact_mut(3).zip(mask_row).enumerate() {
+                if mask_value == 0 {
+                    continue;
+                }
+                let alpha = mask_value as f64 / 255.0;
+                let target = target_at(x as i32, y as i32);
+
+                let original_lightness = pixel_lightness(bgr_pixel[2], bgr_pixel[1], bgr_pixel[0]);
+                let delta_lightness = target.lightness - original_lightness;
+                let transformed_lightness =
+                    (original_lightness + non_linear_transform(delta_lightness)).clamp(0.0, 100.0);
+
+                let rgb = hsl_to_rgb(target.hue, target.saturation, transformed_lightness);
+                let bgr = [rgb[2], rgb[1], rgb[0]];
+                for c in 0..3 {
+                    bgr_pixel[c] = blend(bgr_pixel[c], bgr[c] as f64, alpha);
+                }
+            }
+        });
+    Ok(())
+}
+
+pub fn apply_texture(
+    original_image: &mut Mat,
+    mask: &Mat,
+    luminance: &Mat,
+    texture_image: &Mat,
+    texture: &Texture,
+) -> Result<(), opencv::Error> {
+    let bounds = imgproc::bounding_rect(mask)?;
+    if bounds.width == 0 || bounds.height == 0 || texture_image.empty() {
+        return Ok(());
+    }
+
+    let size = match texture.mode {
+        TextureMode::Tile => Size::new(
+            ((texture_image.cols() as f64 * texture.scale).round() as i32).max(1),
+            ((texture_image.rows() as f64 * texture.scale).round() as i32).max(1),
+        ),
+        TextureMode::Scale => Size::new(bounds.width, bounds.height),
+    };
+    let mut scaled = Mat::default();
+    imgproc::resize(
+        texture_image,
+        &mut scaled,
+        size,
+        0.0,
+        0.0,
+        imgproc::INTER_AREA,
+    )?;
+
+    // Texels are multiplied by how much brighter or darker each pixel is than the
+    // average paint, so reflections and shadows of the base frame stay visible
+    let mean_luminance = opencv::core::mean(luminance, mask)?[0].max(1.0);
+
+    let width = original_image.cols() as usize;
+    let texture_width = scaled.cols();
+    let texture_height = scaled.rows();
+    make_continuous(original_image)?;
+    let (mut mask_copy, mut luminance_copy) = (Mat::default(), Mat::default());
+    let texture_data = scaled.data_bytes()?;
+    let mask_data = continuous_data(mask, &mut mask_copy)?;
+    let luminance_data = continuous_data(luminance, &mut luminance_copy)?;
+    let image_data = original_image.data_bytes_mut()?;
+
+    image_data
+        .par_chunks_mut(width * 3)
+        .zip(mask_data.par_chunks(width))
+        .zip(luminance_data.par_chunks(width))
+        .enumerate()
+        .for_each(|(y, ((row, mask_row), luminance_row))| {
+            let ty = (y as i32 - bounds.y).rem_euclid(texture_height) as usize;
+            let row_start = ty * texture_width as usize * 3;
+            let texture_row = &texture_data[row_start..row_start + texture_width as usize * 3];
+
+            for (x, bgr_pixel) in row.chunks_exact_mut(3).enumerate() {
+                if mask_row[x] == 0 {
+                    continue;
+                }
+                let alpha = mask_row[x] as f64 / 255.0;
+                let tx = (x as i32 - bounds.x).rem_euclid(texture_width) as usize;
+                let shade = luminance_row[x] as f64 / mean_luminance;
+                for c in 0..3 {
+                    let texel = texture_row[tx * 3 + c] as f64;
+                    bgr_pixel[c] = blend(bgr_pixel[c], texel * shade, alpha);
+                }
+            }
+        });
+    Ok(())
+}
+
+/// A decal as `apply_decal` reads it, 8-bit BGRA. Uploads are stored as RGBA,
+/// but grayscale, gray with alpha, BGR and 16-bit images are converted too.
+pub fn decal_to_bgra(decal_image: &Mat) -> Result<Mat, opencv::Error> {
+    let unsupported = || {
+        opencv::Error::new(
+            opencv::core::StsUnsupportedFormat,
+            format!("Unsupported decal image type {}", decal_image.typ()),
+        )
+    };
+    let mut eight_bit = Mat::default();
+    match decal_image.depth() {
+        CV_8U => eight_bit = decal_image.try_clone()?,
+        CV_16U => decal_image.convert_to(&mut eight_bit, CV_8U, 1.0 / 257.0, 0.0)?,
+        _ => return Err(unsupported()),
+    }
+
+    let mut bgra = Mat::default();
+    match eight_bit.channels() {
+        4 => return Ok(eight_bit),
+        3 => imgproc::cvt_color(&eight_bit, &mut bgra, imgproc::COLOR_BGR2BGRA, 0)?,
+        1 => imgproc::cvt_color(&eight_bit, &mut bgra, imgproc::COLOR_GRAY2BGRA, 0)?,
+        2 => {
+            let mut planes = Vector::<Mat>::new();
+            opencv::core::split(&eight_bit, &mut planes)?;
+            let gray = planes.get(0)?;
+            let alpha = planes.get(1)?;
+            let bgra_planes =
+                Vector::<Mat>::from_iter([gray.try_clone()?, gray.try_clone()?, gray, alpha]);
+            opencv::core::merge(&bgra_planes, &mut bgra)?;
+        }
+        _ => return Err(unsupported()),
+    }
+    Ok(bgra)
+}
+
+/// Places a decal on the frame. Expects it as 8-bit BGRA, see `decal_to_bgra`.
+pub fn apply_decal(
+    original_image: &mut Mat,
+    mask: &Mat,
+    luminance: &Mat,
+    decal_image: &Mat,
+    decal: &DecalPlacement,
+) -> Result<(), opencv::Error> {
+    if decal_image.empty() {
+        return Ok(());
+    }
+    let frame_width = original_image.cols();
+    let frame_height = original_image.rows();
+
+    // Rotate and scale around the decal's center, then move that center onto the placement
+    let decal_center = Point2f::new(
+        decal_image.cols() as f32 / 2.0,
+        decal_image.rows() as f32 / 2.0,
+    );
+    let factor = decal.scale * frame_width as f64 / decal_image.cols() as f64;
+    let mut transform = imgproc::get_rotation_matrix_2d(decal_center, -decal.rotation, factor)?;
+    *transform.at_2d_mut::<f64>(0, 2)? += decal.x * frame_width as f64 - decal_center.x as f64;
+    *transform.at_2d_mut::<f64>(1, 2)? += decal.y * frame_height as f64 - decal_center.y as f64;
+
+    let mut placed = Mat::default();
+    imgproc::warp_affine(
+        decal_image,
+        &mut placed,
+        &transform,
+        Size::new(frame_width, frame_height),
+        imgproc::INTER_LINEAR,
+        opencv::core::BORDER_CONSTANT,
+        Scalar::default(),
+    )?;
+
+    let mean_luminance = opencv::core::mean(luminance, mask)?[0].max(1.0);
+
+    let width = frame_width as usize;
+    make_continuous(original_image)?;
+    let (mut mask_copy, mut luminance_copy) = (Mat::default(), Mat::default());
+    let placed_data = placed.data_bytes()?;
+    let mask_data = continuous_data(mask, &mut mask_copy)?;
+    let luminance_data = continuous_data(luminance, &mut luminance_copy)?;
+    let image_data = original_image.data_bytes_mut()?;
+
+    image_data
+        .par_chunks_mut(width * 3)
+        .zip(placed_data.par_chunks(width * 4))
+        .zip(mask_data.par_chunks(width))
+        .zip(luminance_data.par_chunks(width))
+        .for_each(|(((row, decal_row), mask_row), luminance_row)| {
+            for (x, bgr_pixel) in row.chunks_exact_mut(3).enumerate() {
+                let decal_pixel = &decal_row[x * 4..x * 4 + 4];
+                let alpha = (decal_pixel[3] as f64 / 255.0) * (mask_row[x] as f64 / 255.0);
+                if alpha <= 0.0 {
+                    continue;
+                }
+                let shade = luminance_row[x] as f64 / mean_luminance;
+                for c in 0..3 {
+                    bgr_pixel[c] = blend(bgr_pixel[c], decal_pixel[c] as f64 * shade, alpha);
+                }
+            }
+        });
+    Ok(())
+}
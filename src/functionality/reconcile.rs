@@ -0,0 +1,232 @@
+use crate::db::Connection;
+use crate::handle_errors::ReconcileError;
+use crate::storage::{is_key, split_key, Storage, StoredObject};
+use crate::types::image::{Image, ImageId};
+use crate::types::reconcile::{MissingBlobs, ReconcileOptions, ReconcileReport};
+use std::collections::{HashMap, HashSet};
+use time::{Duration, OffsetDateTime};
+
+/// Brings storage and the `image` table back in line: deletes render objects
+/// that no image row refers to, render rows whose objects are gone and
+/// anonymous renders past their retention. With `dry_run` it only reports.
+///
+/// Base sets are never deleted, only reported when their objects are missing.
+pub async fn reconcile(
+    db: &Connection,
+    storage: &Storage,
+    options: &ReconcileOptions,
+) -> Result<ReconcileReport, ReconcileError> {
+    let mut report = ReconcileReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+
+    // Rows are read before storage is listed. A row is inserted only after all
+    // of its objects are uploaded, so every object of a row read here shows up
+    // in the listing unless it is really gone.
+    let images = db.get_images().await?;
+    // Animations are stored under the prefix of their image and go with it
+    let imageids: Vec<ImageId> = images.iter().map(|image| image.id.clone()).collect();
+    let mut animations: HashMap<ImageId, Vec<String>> = HashMap::new();
+    for animation in db.get_animations_of_images(&imageids).await? {
+        animations
+            .entry(animation.imageid)
+            .or_default()
+            .push(animation.url);
+    }
+    let expired: HashSet<ImageId> = match options.anonymous_retention_days {
+        Some(days) => db
+            .get_expired_anonymous_images(days)
+            .await?
+            .into_iter()
+            .collect(),
+        None => HashSet::new(),
+    };
+
+    let legacy_containers: Vec<String> = storage
+        .list_containers()
+        .await?
+        .into_iter()
+        .filter(|container| is_legacy_container(container))
+        .collect();
+    let mut listed_containers = HashSet::new();
+    let mut objects = storage.list(storage.container(), "").await?;
+    listed_containers.insert(storage.container().to_string());
+    for container in &legacy_containers {
+        objects.extend(storage.list(container, "").await?);
+        listed_containers.insert(container.clone());
+    }
+    // Base sets can live in containers of their own; they are listed only to
+    // check that their frames exist.
+    for image in &images {
+        for reference in image.references() {
+            let Ok((container, _)) = split_key(reference) else {
+                continue;
+            };
+            if !is_key(reference) || !listed_containers.insert(container.to_string()) {
+                continue;
+            }
+            match storage.list(container, "").await {
+                Ok(listed) => objects.extend(listed),
+                Err(e) => {
+                    report
+                        .errors
+                        .push(format!("Cannot list container {} {}", container, e));
+                    listed_containers.remove(container);
+                }
+            }
+        }
+    }
+    let existing: HashSet<&str> = objects.iter().map(|object| object.key.as_str()).collect();
+
+    let mut removed_images = Vec::new();
+    let mut removed_groups = HashSet::new();
+    let mut kept_groups = HashSet::new();
+    for image in &images {
+        let render = is_render(storage, image);
+        let missing: Vec<String> = image
+            .references()
+            .into_iter()
+            .filter(|reference| is_key(reference) && !existing.contains(reference))
+            .filter(|reference| match split_key(reference) {
+                Ok((container, _)) => listed_containers.contains(container),
+                Err(_) => false,
+            })
+            .map(|reference| reference.to_string())
+            .collect();
+        let is_expired = render && image.userid.is_none() && expired.contains(&image.id);
+
+        if !missing.is_empty() {
+            report.missing_blobs.push(MissingBlobs {
+                imageid: image.id.clone(),
+                keys: missing.clone(),
+                removed: render,
+            });
+        }
+        if is_expired {
+            report.expired_images.push(image.id.clone());
+        }
+
+        let image_animations = animations
+            .get(&image.id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let groups = image
+            .references()
+            .into_iter()
+            .chain(image_animations.iter().map(|url| url.as_str()))
+            .filter_map(|reference| render_group(storage, reference));
+        if render && (is_expired || !missing.is_empty()) {
+            removed_images.push(image.id.clone());
+            removed_groups.extend(groups);
+            // Animations from before they were stored with their render
+            for url in image_animations.iter().filter(|url| !is_key(url)) {
+                report.errors.push(format!(
+                    "Animation {} of image {} is not stored with its render, delete it by hand",
+                    url, image.id.0
+                ));
+            }
+        } else {
+            kept_groups.extend(groups);
+        }
+    }
+    // Never delete objects another remaining row still refers to
+    removed_groups.retain(|group| !kept_groups.contains(group));
+
+    let cutoff = OffsetDateTime::now_utc() - Duration::hours(options.orphan_grace_hours);
+    let mut deletable: Vec<&StoredObject> = Vec::new();
+    let mut recent_groups = HashSet::new();
+    for object in &objects {
+        let Some(group) = render_group(storage, &object.key) else {
+            continue;
+        };
+        if kept_groups.contains(&group) {
+            continue;
+        }
+        if removed_groups.contains(&group) {
+            report.removed_objects.push(object.key.clone());
+        } else if object.last_modified < cutoff {
+            report.orphaned_objects.push(object.key.clone());
+            report.orphaned_bytes += object.size;
+        } else {
+            recent_groups.insert(group);
+            continue;
+        }
+        deletable.push(object);
+    }
+    report.orphaned_containers = legacy_containers
+        .into_iter()
+        .filter(|container| !kept_groups.contains(container) && !recent_groups.contains(container))
+        .collect();
+
+    if options.dry_run {
+        return Ok(report);
+    }
+
+    // Rows go before render objects: if deleting objects fails halfway, what
+    // is left over are orphans the next run picks up, not rows pointing at
+    // deleted objects.
+    if !removed_images.is_empty() {
+        db.delete_images(&removed_images).await?;
+    }
+    for object in deletable {
+        let in_removed_container = split_key(&object.key)
+            .map(|(container, _)| report.orphaned_containers.iter().any(|c| c == container))
+            .unwrap_or(false);
+        if in_removed_container {
+            continue;
+        }
+        if let Err(e) = storage.delete(&object.key).await {
+            report
+                .errors
+                .push(format!("Cannot delete {} {}", object.key, e));
+        }
+    }
+    for container in &report.orphaned_containers {
+        if let Err(e) = storage.delete_container(container).await {
+            report
+                .errors
+                .push(format!("Cannot delete container {} {}", container, e));
+        }
+    }
+    Ok(report)
+}
+
+/// Renders used to get a container of their own, named by a random UUID.
+fn is_legacy_container(container: &str) -> bool {
+    uuid::Uuid::parse_str(container).is_ok()
+}
+
+/// The unit a render's objects are stored and deleted in: its prefix
+/// `{container}/renders/{user}/{imageid}`, or its whole container in the old
+/// layout. `None` for objects that do not belong to a render.
+fn render_group(storage: &Storage, key: &str) -> Option<String> {
+    if !is_key(key) {
+        return None;
+    }
+    let (container, path) = split_key(key).ok()?;
+    if container == storage.container() {
+        let mut parts = path.splitn(4, '/');
+        match (parts.next(), parts.next(), parts.next(), parts.next()) {
+            (Some("renders"), Some(user), Some(imageid), Some(_)) => {
+                Some(format!("{}/renders/{}/{}", container, user, imageid))
+            }
+            _ => None,
+        }
+    } else if is_legacy_container(container) {
+        Some(container.to_string())
+    } else {
+        None
+    }
+}
+
+/// Renders point at the image set they were made from. Renders from before
+/// that was recorded are recognized by where their objects are stored.
+fn is_render(storage: &Storage, image: &Image) -> bool {
+    let references = image.references();
+    image.base_imageid.is_some()
+        || (!references.is_empty()
+            && references
+                .iter()
+                .all(|reference| render_group(storage, reference).is_some()))
+}
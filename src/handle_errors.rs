@@ -1,11 +1,48 @@
+use crate::types::color_wall::MAX_PALETTE_COLORS;
 use std::fmt::Formatter;
+use warp::http::StatusCode;
 use warp::reject::Reject;
+use warp::{Rejection, Reply};
 
 #[derive(Debug)]
 pub enum Error {
     ParseError(std::num::ParseIntError),
     MissingParams,
     ColorSwapError,
+    InvalidGradient,
+    InvalidTexture,
+    ConflictingFinish,
+    InvalidDecal,
+    InvalidPreview,
+    FrameCountMismatch,
+    FrameSizeMismatch,
+    InvalidOutput,
+    InvalidAnimation,
+    AnimationError,
+    InvalidComparison,
+    NoPaintArea,
+    InvalidColorWall,
+    TooManyColors,
+    Fetch(FetchError),
+}
+
+/// Why a source image could not be fetched or decoded.
+#[derive(Debug)]
+pub enum FetchError {
+    InvalidUrl(String),
+    /// The URL points at a host that is not in the fetch allowlist.
+    HostNotAllowed(String),
+    Timeout,
+    Request(reqwest::Error),
+    /// The host answered with this non-success status.
+    Status(u16),
+    /// The host answered with something that is not an image.
+    ContentType(String),
+    /// The body is larger than this many bytes.
+    TooLarge(u64),
+    /// The decoded image would have this many pixels, more than allowed.
+    TooManyPixels(u64),
+    Decode(String),
 }
 
 #[derive(Debug)]
@@ -13,6 +50,22 @@ pub enum LoginError {
     InvalidCredentials,
 }
 
+#[derive(Debug)]
+pub enum StorageError {
+    /// A stored reference that is not of the form `{container}/{path}`.
+    InvalidKey(String),
+    /// Data that could not be encoded or serialized for upload.
+    InvalidData(String),
+    Azure(azure_core::Error),
+    S3(s3::error::S3Error),
+}
+
+#[derive(Debug)]
+pub enum ReconcileError {
+    Database(sqlx::Error),
+    Storage(StorageError),
+}
+
 impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match *self {
@@ -25,10 +78,105 @@ impl std::fmt::Display for Error {
             Error::ColorSwapError => {
                 write!(f, "Cannot swap colors")
             }
+            Error::InvalidGradient => {
+                write!(f, "Gradient needs at least two stops with offsets between 0 and 1")
+            }
+            Error::InvalidTexture => {
+                write!(f, "Texture needs an image and a positive scale")
+            }
+            Error::ConflictingFinish => {
+                write!(f, "Only one of gradient and texture can be requested")
+            }
+            Error::InvalidDecal => {
+                write!(f, "Decal needs an image, a position inside the frame and a positive scale")
+            }
+            Error::InvalidPreview => {
+                write!(f, "Preview needs a frame of the image set and a width between 1 and 1024")
+            }
+            Error::FrameCountMismatch => {
+                write!(f, "Number of frames does not match the image set")
+            }
+            Error::FrameSizeMismatch => {
+                write!(f, "Frames of the render do not all have the same size")
+            }
+            Error::InvalidOutput => {
+                write!(f, "Output needs a quality between 1 and 100 and at least one size")
+            }
+            Error::InvalidAnimation => {
+                write!(f, "Animation needs frames, a delay between 10 and 10000 ms and a width up to 1280")
+            }
+            Error::AnimationError => {
+                write!(f, "Cannot encode animation")
+            }
+            Error::InvalidComparison => {
+                write!(f, "Comparison needs a rendered image, a frame it has and a split between 0 and 1")
+            }
+            Error::NoPaintArea => {
+                write!(f, "No paint area found in the base frames")
+            }
+            Error::InvalidColorWall => {
+                write!(f, "Color wall needs a filter matching at least one color")
+            }
+            Error::TooManyColors => {
+                write!(f, "Color wall palette can have at most {} colors", MAX_PALETTE_COLORS)
+            }
+            Error::Fetch(ref err) => {
+                write!(f, "Cannot fetch source image: {}", err)
+            }
         }
     }
 }
 
+impl std::fmt::Display for FetchError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            FetchError::InvalidUrl(ref url) => {
+                write!(f, "invalid URL {}", url)
+            }
+            FetchError::HostNotAllowed(ref host) => {
+                write!(f, "host {} is not allowed", host)
+            }
+            FetchError::Timeout => {
+                write!(f, "timed out")
+            }
+            FetchError::Request(ref err) => {
+                write!(f, "request failed {}", err)
+            }
+            FetchError::Status(status) => {
+                write!(f, "host answered with status {}", status)
+            }
+            FetchError::ContentType(ref content_type) => {
+                write!(f, "{} is not an image", content_type)
+            }
+            FetchError::TooLarge(limit) => {
+                write!(f, "image is larger than {} bytes", limit)
+            }
+            FetchError::TooManyPixels(pixels) => {
+                write!(f, "image has {} pixels, more than allowed", pixels)
+            }
+            FetchError::Decode(ref err) => {
+                write!(f, "cannot decode image {}", err)
+            }
+        }
+    }
+}
+
+impl From<reqwest::Error> for FetchError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            FetchError::Timeout
+        } else {
+            FetchError::Request(err)
+        }
+    }
+}
+
+impl From<FetchError> for Error {
+    fn from(err: FetchError) -> Self {
+        Error::Fetch(err)
+    }
+}
+
 impl std::fmt::Display for LoginError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match *self {
@@ -39,6 +187,142 @@ impl std::fmt::Display for LoginError {
     }
 }
 
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            StorageError::InvalidKey(ref key) => {
+                write!(f, "Invalid storage key {}", key)
+            }
+            StorageError::InvalidData(ref err) => {
+                write!(f, "Cannot prepare data for storage {}", err)
+            }
+            StorageError::Azure(ref err) => {
+                write!(f, "Azure storage error {}", err)
+            }
+            StorageError::S3(ref err) => {
+                write!(f, "S3 storage error {}", err)
+            }
+        }
+    }
+}
+
+impl From<s3::error::S3Error> for StorageError {
+    fn from(err: s3::error::S3Error) -> Self {
+        StorageError::S3(err)
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl StorageError {
+    /// Whether the object or its container does not exist.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            StorageError::Azure(err) => matches!(
+                err.kind(),
+                azure_core::error::ErrorKind::HttpResponse { status, .. } if *status as u16 == 404
+            ),
+            StorageError::S3(s3::error::S3Error::HttpFailWithBody(status, _)) => *status == 404,
+            _ => false,
+        }
+    }
+
+    /// Whether a conditional request failed because the object has changed.
+    pub fn is_precondition_failed(&self) -> bool {
+        match self {
+            StorageError::Azure(err) => matches!(
+                err.kind(),
+                azure_core::error::ErrorKind::HttpResponse { status, .. } if *status as u16 == 412
+            ),
+            StorageError::S3(s3::error::S3Error::HttpFailWithBody(status, _)) => *status == 412,
+            _ => false,
+        }
+    }
+
+    /// Whether trying the same request again may succeed: timeouts, throttling,
+    /// server errors and dropped connections.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            StorageError::InvalidKey(_) | StorageError::InvalidData(_) => false,
+            StorageError::Azure(err) => match err.kind() {
+                azure_core::error::ErrorKind::HttpResponse { status, .. } => {
+                    let status = *status as u16;
+                    status == 408 || status == 429 || status >= 500
+                }
+                azure_core::error::ErrorKind::Io => true,
+                _ => false,
+            },
+            StorageError::S3(err) => match err {
+                s3::error::S3Error::HttpFailWithBody(status, _) => {
+                    *status == 408 || *status == 429 || *status >= 500
+                }
+                s3::error::S3Error::Reqwest(err) => err.is_timeout() || err.is_connect(),
+                s3::error::S3Error::Io(_) => true,
+                _ => false,
+            },
+        }
+    }
+}
+
+impl From<azure_core::Error> for StorageError {
+    fn from(err: azure_core::Error) -> Self {
+        StorageError::Azure(err)
+    }
+}
+
+impl std::fmt::Display for ReconcileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            ReconcileError::Database(ref err) => {
+                write!(f, "Database error {}", err)
+            }
+            ReconcileError::Storage(ref err) => {
+                write!(f, "{}", err)
+            }
+        }
+    }
+}
+
+impl From<sqlx::Error> for ReconcileError {
+    fn from(err: sqlx::Error) -> Self {
+        ReconcileError::Database(err)
+    }
+}
+
+impl From<StorageError> for ReconcileError {
+    fn from(err: StorageError) -> Self {
+        ReconcileError::Storage(err)
+    }
+}
+
 impl Reject for Error {}
 
 impl Reject for LoginError {}
+
+impl Error {
+    /// The status a request failing with this error is answered with, or
+    /// `None` to leave the rejection to warp.
+    fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::TooManyColors => Some(StatusCode::BAD_REQUEST),
+            Error::Fetch(FetchError::InvalidUrl(_) | FetchError::HostNotAllowed(_)) => {
+                Some(StatusCode::BAD_REQUEST)
+            }
+            Error::Fetch(FetchError::Timeout) => Some(StatusCode::GATEWAY_TIMEOUT),
+            // The source host failed or sent something that is not a usable image
+            Error::Fetch(_) => Some(StatusCode::BAD_GATEWAY),
+            _ => None,
+        }
+    }
+}
+
+/// Answers rejections with an `Error` that has a status, see `Error::status`.
+/// Everything else is passed on unchanged.
+pub async fn return_error(r: Rejection) -> Result<impl Reply, Rejection> {
+    if let Some(error) = r.find::<Error>() {
+        if let Some(status) = error.status() {
+            return Ok(warp::reply::with_status(error.to_string(), status));
+        }
+    }
+    Err(r)
+}
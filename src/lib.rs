@@ -0,0 +1,7 @@
+pub mod config;
+pub mod db;
+pub mod functionality;
+pub mod handle_errors;
+pub mod metrics;
+pub mod storage;
+pub mod types;
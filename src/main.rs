@@ -1,13 +1,27 @@
-mod db;
-mod functionality;
-mod handle_errors;
-mod types;
-
-use crate::functionality::{color_swap, container_generation};
-use crate::handle_errors::LoginError;
-use crate::types::carparams::{extract_car_params, CarParams};
-use crate::types::image::{Image, NewImage};
-use crate::types::user::{NewUser, User, UserCredentials, UserId};
+use carcaro::config::Config;
+use carcaro::db;
+use carcaro::functionality::{
+    animation, color_swap, color_wall, comparison, container_generation, fetch, frame_cache,
+    frames, preflight, preview,
+};
+use carcaro::handle_errors::LoginError;
+use carcaro::metrics;
+use carcaro::storage::Storage;
+use carcaro::types::accuracy::AccuracyReport;
+use carcaro::types::animation::{AnimationRequest, NewAnimation};
+use carcaro::types::carparams::{extract_car_params, CarParams};
+use carcaro::types::color_wall::ColorWallRequest;
+use carcaro::types::comparison::ComparisonQuery;
+use carcaro::types::decal::{DecalPlacements, UploadedDecal};
+use carcaro::types::frame::{BaseFrame, FrameQuery};
+use carcaro::types::image::NewImage;
+use carcaro::types::image_request::{ImageRequest, RenderRequest};
+use carcaro::types::preflight::PreflightRequest;
+use carcaro::types::preview::PreviewRequest;
+use carcaro::types::texture::UploadedTexture;
+use carcaro::handle_errors::{return_error, Error};
+use carcaro::types::user::{NewUser, User, UserCredentials, UserId};
+use image::DynamicImage;
 use reqwest::StatusCode;
 use std::collections::HashMap;
 use std::net::ToSocketAddrs;
@@ -15,9 +29,25 @@ use warp::{http::Method, Filter, Rejection, Reply};
 
 #[tokio::main]
 async fn main() {
-    let db = db::Connection::new("postgres://postgres:a@localhost:5432/carcaro").await;
+    let config = Config::from_env();
+    let db = db::Connection::new(&config.database_url).await;
+    match db.fail_running_color_walls().await {
+        Ok(0) => {}
+        Ok(count) => println!("Marked {} interrupted color walls as failed", count),
+        Err(e) => eprintln!("Database error {:?}", e),
+    }
     let db_filter = warp::any().map(move || db.clone());
 
+    let storage = Storage::new(&config.storage);
+    storage
+        .ensure_container(storage.container(), false)
+        .await
+        .expect("Failed to create render container");
+    let storage_filter = warp::any().map(move || storage.clone());
+
+    fetch::init(&config.fetch);
+    frame_cache::init(&config.frame_cache).expect("Failed to open frame cache");
+
     let cors = warp::cors()
         .allow_any_origin()
         .allow_headers(vec![
@@ -48,6 +78,7 @@ async fn main() {
         .and(warp::path::end())
         .and(warp::query())
         .and(db_filter.clone())
+        .and(storage_filter.clone())
         .and_then(get_car_to_visualize);
 
     let get_colors = warp::get()
@@ -67,9 +98,127 @@ async fn main() {
         .and(warp::path("cars"))
         .and(warp::path("newimage"))
         .and(db_filter.clone())
+        .and(storage_filter.clone())
         .and(warp::body::json())
         .and_then(post_new_image);
 
+    let post_preview = warp::post()
+        .and(warp::path("cars"))
+        .and(warp::path("preview"))
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and(storage_filter.clone())
+        .and(warp::body::json())
+        .and_then(post_preview);
+
+    let post_preflight = warp::post()
+        .and(warp::path("cars"))
+        .and(warp::path("preflight"))
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and(storage_filter.clone())
+        .and(warp::body::json())
+        .and_then(post_preflight);
+
+    let post_animation = warp::post()
+        .and(warp::path("images"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("animations"))
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and(storage_filter.clone())
+        .and(warp::body::json())
+        .and_then(post_animation);
+
+    let get_animations = warp::get()
+        .and(warp::path("images"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("animations"))
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and(storage_filter.clone())
+        .and_then(get_animations);
+
+    let get_comparison = warp::get()
+        .and(warp::path("images"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("compare"))
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(db_filter.clone())
+        .and(storage_filter.clone())
+        .and_then(get_comparison);
+
+    let get_accuracy = warp::get()
+        .and(warp::path("images"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("accuracy"))
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and_then(get_accuracy);
+
+    let get_frame = warp::get()
+        .and(warp::path("images"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("frames"))
+        .and(warp::path::param::<usize>())
+        .and(warp::path::end())
+        .and(warp::query())
+        .and(frame_conditions())
+        .and(db_filter.clone())
+        .and(storage_filter.clone())
+        .and_then(get_frame);
+
+    let post_color_wall = warp::post()
+        .and(warp::path("images"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("colorwalls"))
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and(storage_filter.clone())
+        .and(warp::body::json())
+        .and_then(post_color_wall);
+
+    let get_color_wall = warp::get()
+        .and(warp::path("colorwalls"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and(storage_filter.clone())
+        .and_then(get_color_wall);
+
+    let post_new_texture = warp::post()
+        .and(warp::path("textures"))
+        .and(warp::path::end())
+        .and(storage_filter.clone())
+        .and(warp::body::content_length_limit(1024 * 1024 * 16))
+        .and(warp::body::bytes())
+        .and_then(post_new_texture);
+
+    let post_new_decal = warp::post()
+        .and(warp::path("decals"))
+        .and(warp::path::end())
+        .and(storage_filter.clone())
+        .and(warp::body::content_length_limit(1024 * 1024 * 4))
+        .and(warp::body::bytes())
+        .and_then(post_new_decal);
+
+    let get_decal_placements = warp::get()
+        .and(warp::path("cars"))
+        .and(warp::path::param::<i32>())
+        .and(warp::path("decals"))
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and_then(get_decal_placements);
+
+    let post_decal_placements = warp::post()
+        .and(warp::path("cars"))
+        .and(warp::path("decals"))
+        .and(warp::path::end())
+        .and(db_filter.clone())
+        .and(warp::body::json())
+        .and_then(post_decal_placements);
+
     let post_new_user = warp::post()
         .and(warp::path("user"))
         .and(warp::path("newuser"))
@@ -84,18 +233,46 @@ async fn main() {
         .and(warp::body::json())
         .and_then(post_user_to_sign_in);
 
+    let get_metrics = warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and_then(get_metrics);
+
     let routes = get_cars
         .or(get_cars_to_visualize)
         .or(get_colors)
         .or(get_user_favorites)
         .or(post_user_to_sign_in)
         .or(post_new_image)
+        .or(post_preview)
+        .or(post_preflight)
+        .or(post_animation)
+        .or(get_animations)
+        .or(get_comparison)
+        .or(get_accuracy)
+        .or(get_frame)
+        .or(post_color_wall)
+        .or(get_color_wall)
+        .or(post_new_texture)
+        .or(post_new_decal)
+        .or(get_decal_placements)
+        .or(post_decal_placements)
         .or(post_new_user)
-        .with(cors);
+        .or(get_metrics)
+        .with(cors)
+        .recover(return_error);
 
     warp::serve(routes).run(([127, 0, 0, 1], 7071)).await;
 }
 
+pub async fn get_metrics() -> Result<impl Reply, Rejection> {
+    Ok(warp::reply::with_header(
+        metrics::render(),
+        "Content-Type",
+        "text/plain; version=0.0.4",
+    ))
+}
+
 pub async fn get_cars_with_images(db: db::Connection) -> Result<impl Reply, Rejection> {
     let res = match db.get_cars_with_images().await {
         Ok(res) => res,
@@ -131,6 +308,7 @@ pub async fn post_user_to_sign_in(
 pub async fn get_car_to_visualize(
     params: HashMap<String, String>,
     db: db::Connection,
+    storage: Storage,
 ) -> Result<impl Reply, Rejection> {
     let mut car_params = CarParams::default();
     if !params.is_empty() {
@@ -143,6 +321,7 @@ pub async fn get_car_to_visualize(
         Ok(res) => res,
         Err(e) => return Err(warp::reject::not_found()),
     };
+    let res = res.with_signed_urls(|url| storage.signed_url(url));
     Ok(warp::reply::json(&res))
 }
 
@@ -164,29 +343,450 @@ pub async fn get_user_favorites(
 }
 
 
-pub async fn post_new_image(db: db::Connection, image: Image) -> Result<impl Reply, Rejection> {
+pub async fn post_new_image(
+    db: db::Connection,
+    storage: Storage,
+    image: RenderRequest,
+) -> Result<impl Reply, Rejection> {
+    let finish = image.finish()?;
+    let decals = image.decals()?;
+    let output = image.output()?;
+    container_generation::check_assets(&storage, &finish, &decals)?;
     let image_request = match db.extract_image(image.id.0).await {
         Ok(image_request) => image_request,
         Err(e) => return Err(warp::reject::not_found()),
     };
-    color_swap::color_swap(image_request.url, image.colors).await?;
+    let base_frames = base_frames(&storage, image_request)?;
+    let (rendered, accuracy) = color_swap::color_swap(base_frames, finish, decals).await?;
 
-    let id = uuid::Uuid::new_v4();
-    let new_image_urls = container_generation::generate_and_upload(id.to_string())
+    let id = match db.reserve_image_id().await {
+        Ok(id) => id,
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    let prefix = container_generation::render_prefix(&storage, image.userid, id.0);
+    let upload = match container_generation::upload_frames(&storage, &prefix, rendered, &output)
         .await
-        .unwrap();
+    {
+        Ok(upload) => upload,
+        Err(e) => {
+            eprintln!("Error uploading render {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
     let new_image = NewImage {
-        url: new_image_urls,
+        url: upload.frames.iter().filter_map(|frame| frame.largest_url()).collect(),
+        frame_count: upload.frames.len() as i32,
+        base_imageid: Some(image.id.0),
         colors: image.colors,
-        userid: image.userid
+        userid: image.userid,
+        variants: upload.frames,
+        sprite_sheet: upload.sprite_sheet,
+        accuracy,
+    };
+
+    let res = match db.add_new_image(id, new_image).await {
+        Ok(res) => res,
+        Err(e) => {
+            if let Err(e) = container_generation::remove_render(&storage, &prefix).await {
+                eprintln!("Error rolling back render {} {}", prefix, e);
+            }
+            return Err(warp::reject::not_found());
+        }
+    };
+    let res = res.with_signed_urls(|url| storage.signed_url(url));
+    Ok(warp::reply::json(&res))
+}
+pub async fn post_preview(
+    db: db::Connection,
+    storage: Storage,
+    request: PreviewRequest,
+) -> Result<impl Reply, Rejection> {
+    let finish = request.finish()?;
+    container_generation::check_assets(&storage, &finish, &request.decals)?;
+    let image_request = match db.extract_image(request.id.0).await {
+        Ok(image_request) => image_request,
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    let base_frame = match base_frames(&storage, image_request)?.into_iter().nth(request.frame) {
+        Some(base_frame) => base_frame,
+        None => return Err(warp::reject::custom(Error::InvalidPreview)),
     };
 
-    let res = match db.add_new_image(new_image).await {
+    let content_type = request.format.content_type();
+    let res = match preview::render_preview(request, base_frame).await {
         Ok(res) => res,
+        Err(e) => {
+            eprintln!("Error rendering preview {}", e);
+            return Err(warp::reject::custom(e));
+        }
+    };
+    Ok(warp::reply::with_header(res, "Content-Type", content_type))
+}
+
+pub async fn post_preflight(
+    db: db::Connection,
+    storage: Storage,
+    request: PreflightRequest,
+) -> Result<impl Reply, Rejection> {
+    let targets = request.targets()?;
+    let image_request = match db.extract_image(request.id.0).await {
+        Ok(image_request) => image_request,
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    let base_frames = base_frames(&storage, image_request)?;
+    let res = match preflight::preflight(base_frames, targets).await {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Error running pre-flight check {}", e);
+            return Err(warp::reject::custom(e));
+        }
+    };
+    Ok(warp::reply::json(&res))
+}
+
+pub async fn post_animation(
+    imageid: i32,
+    db: db::Connection,
+    storage: Storage,
+    request: AnimationRequest,
+) -> Result<impl Reply, Rejection> {
+    request.validate()?;
+    let image = match db.get_image(imageid).await {
+        Ok(image) => image.with_signed_urls(|url| storage.signed_url(url)),
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    let prefix = container_generation::render_prefix(&storage, image.userid, image.id.0);
+
+    let encoded = match animation::export_animation(image.url, &request).await {
+        Ok(encoded) => encoded,
+        Err(e) => {
+            eprintln!("Error exporting animation {}", e);
+            return Err(warp::reject::custom(e));
+        }
+    };
+
+    let url = match container_generation::upload_animation(
+        &storage,
+        &prefix,
+        request.format.extension(),
+        request.format.content_type(),
+        encoded.data,
+    )
+    .await
+    {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("Error uploading animation {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+
+    let new_animation = NewAnimation {
+        imageid: image.id,
+        format: request.format,
+        url,
+        width: encoded.width,
+        height: encoded.height,
+        delay_ms: request.delay_ms,
+    };
+    let res = match db.add_animation(new_animation).await {
+        Ok(res) => res.with_signed_url(|url| storage.signed_url(url)),
         Err(e) => return Err(warp::reject::not_found()),
     };
     Ok(warp::reply::json(&res))
 }
+
+pub async fn get_animations(
+    imageid: i32,
+    db: db::Connection,
+    storage: Storage,
+) -> Result<impl Reply, Rejection> {
+    let res: Vec<_> = match db.get_animations(imageid).await {
+        Ok(res) => res
+            .into_iter()
+            .map(|animation| animation.with_signed_url(|url| storage.signed_url(url)))
+            .collect(),
+        Err(e) => {
+            eprintln!("Error {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    Ok(warp::reply::json(&res))
+}
+
+pub async fn get_comparison(
+    imageid: i32,
+    query: ComparisonQuery,
+    db: db::Connection,
+    storage: Storage,
+) -> Result<impl Reply, Rejection> {
+    query.validate()?;
+    let image = match db.get_image(imageid).await {
+        Ok(image) => image,
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    let base_imageid = image.base_imageid.ok_or(Error::InvalidComparison)?;
+    let base_image = match db.get_image(base_imageid).await {
+        Ok(base_image) => base_image,
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    let (base_frame, recolored_url) = match (base_image.url.get(query.frame), image.url.get(query.frame)) {
+        (Some(base_reference), Some(recolored_url)) => (
+            BaseFrame {
+                reference: base_reference.clone(),
+                url: storage.signed_url(base_reference),
+            },
+            storage.signed_url(recolored_url),
+        ),
+        _ => return Err(warp::reject::custom(Error::InvalidComparison)),
+    };
+
+    let after_caption = if query.captions {
+        let [r, g, b] = image.colors;
+        let hex = format!("{:02X}{:02X}{:02X}", r, g, b);
+        match db.get_color_by_hex(hex.clone()).await {
+            Ok(color) => Some(format!("RAL {} {}", color.ral, color.color_name)),
+            Err(_) => Some(format!("#{}", hex)),
+        }
+    } else {
+        None
+    };
+
+    let content_type = query.format.content_type();
+    let res = match comparison::compare_frames(base_frame, recolored_url, &query, after_caption).await {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Error composing comparison {}", e);
+            return Err(warp::reject::custom(e));
+        }
+    };
+    Ok(warp::reply::with_header(res, "Content-Type", content_type))
+}
+
+fn frame_conditions(
+) -> impl Filter<Extract = (frames::FrameConditions,), Error = Rejection> + Clone {
+    warp::header::optional::<String>("if-none-match")
+        .and(warp::header::optional::<String>("range"))
+        .and(warp::header::optional::<String>("if-range"))
+        .map(|if_none_match, range, if_range| frames::FrameConditions {
+            if_none_match,
+            range,
+            if_range,
+        })
+}
+
+/// Serves one frame of an image from storage, optionally in another size,
+/// with caching headers and support for conditional and range requests.
+pub async fn get_frame(
+    imageid: i32,
+    frame: usize,
+    query: FrameQuery,
+    conditions: frames::FrameConditions,
+    db: db::Connection,
+    storage: Storage,
+) -> Result<impl Reply, Rejection> {
+    let image = match db.get_image(imageid).await {
+        Ok(image) => image,
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    let reference = match image.frame_reference(frame, query.size) {
+        Some(reference) => reference,
+        None => return Err(warp::reject::not_found()),
+    };
+    let render = image.base_imageid.is_some();
+    match frames::serve_frame(&storage, reference, render, conditions).await {
+        Ok(res) => Ok(res),
+        Err(e) if e.is_not_found() => Err(warp::reject::not_found()),
+        Err(e) => {
+            eprintln!("Error serving frame {} of image {} {}", frame, imageid, e);
+            Err(warp::reject::not_found())
+        }
+    }
+}
+
+/// Starts rendering the image set in every color of the filter and returns
+/// the job right away; its progress and index are at `GET /colorwalls/{id}`.
+pub async fn post_color_wall(
+    imageid: i32,
+    db: db::Connection,
+    storage: Storage,
+    request: ColorWallRequest,
+) -> Result<impl Reply, Rejection> {
+    if !request.output.is_valid() {
+        return Err(warp::reject::custom(Error::InvalidOutput));
+    }
+    let catalog = match db.get_colors().await {
+        Ok(catalog) => catalog,
+        Err(e) => {
+            eprintln!("Error {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    let colors = request.filter.select(catalog)?;
+    let image_request = match db.extract_image(imageid).await {
+        Ok(image_request) => image_request,
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    let base_frames = base_frames(&storage, image_request)?;
+
+    let wall = match db.create_color_wall(imageid, request.userid, colors.len() as i32).await {
+        Ok(wall) => wall,
+        Err(e) => return Err(warp::reject::not_found()),
+    };
+    tokio::spawn(color_wall::run_color_wall(
+        db.clone(),
+        storage.clone(),
+        wall.clone(),
+        base_frames,
+        colors,
+        request.userid,
+        request.output,
+    ));
+    Ok(warp::reply::with_status(warp::reply::json(&wall), StatusCode::ACCEPTED))
+}
+
+pub async fn get_color_wall(
+    id: i32,
+    db: db::Connection,
+    storage: Storage,
+) -> Result<impl Reply, Rejection> {
+    let res = match db.get_color_wall(id).await {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Error {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    let res = res.with_signed_urls(|url| storage.signed_url(url));
+    Ok(warp::reply::json(&res))
+}
+
+pub async fn get_accuracy(imageid: i32, db: db::Connection) -> Result<impl Reply, Rejection> {
+    let image = match db.get_image(imageid).await {
+        Ok(image) => image,
+        Err(e) => {
+            eprintln!("Error {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    Ok(warp::reply::json(&AccuracyReport::new(image.id, image.accuracy)))
+}
+
+/// Base frames of an image set, with URLs that can be downloaded.
+fn base_frames(storage: &Storage, image_request: ImageRequest) -> Result<Vec<BaseFrame>, Error> {
+    Ok(image_request
+        .frame_urls()?
+        .iter()
+        .map(|reference| BaseFrame {
+            reference: reference.to_string(),
+            url: storage.signed_url(reference),
+        })
+        .collect())
+}
+
+/// Stores uploads as 8-bit PNGs, RGBA when `alpha` is set and RGB otherwise,
+/// whatever color type they came in.
+fn reencode_as_png(body: &[u8], alpha: bool) -> Option<Vec<u8>> {
+    let image = match image::load_from_memory(body) {
+        Ok(image) if alpha => DynamicImage::ImageRgba8(image.to_rgba8()),
+        Ok(image) => DynamicImage::ImageRgb8(image.to_rgb8()),
+        Err(e) => {
+            eprintln!("Error decoding upload {}", e);
+            return None;
+        }
+    };
+
+    let mut png = std::io::Cursor::new(Vec::new());
+    if let Err(e) = image.write_to(&mut png, image::ImageOutputFormat::Png) {
+        eprintln!("Error encoding upload {}", e);
+        return None;
+    }
+    Some(png.into_inner())
+}
+
+pub async fn post_new_texture(
+    storage: Storage,
+    body: warp::hyper::body::Bytes,
+) -> Result<impl Reply, Rejection> {
+    let png = reencode_as_png(&body, false).ok_or(Error::InvalidTexture)?;
+
+    let url = match container_generation::upload_asset(
+        &storage,
+        container_generation::TEXTURE_CONTAINER,
+        "png",
+        "image/png",
+        png,
+    )
+    .await
+    {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("Error uploading texture {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    Ok(warp::reply::json(&UploadedTexture { url }))
+}
+
+pub async fn post_new_decal(
+    storage: Storage,
+    body: warp::hyper::body::Bytes,
+) -> Result<impl Reply, Rejection> {
+    let png = reencode_as_png(&body, true).ok_or(Error::InvalidDecal)?;
+
+    let url = match container_generation::upload_asset(
+        &storage,
+        container_generation::DECAL_CONTAINER,
+        "png",
+        "image/png",
+        png,
+    )
+    .await
+    {
+        Ok(url) => url,
+        Err(e) => {
+            eprintln!("Error uploading decal {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    Ok(warp::reply::json(&UploadedDecal { url }))
+}
+
+pub async fn get_decal_placements(
+    imageid: i32,
+    db: db::Connection,
+) -> Result<impl Reply, Rejection> {
+    let res = match db.get_decal_placements(imageid).await {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Error {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    Ok(warp::reply::json(&res))
+}
+
+pub async fn post_decal_placements(
+    db: db::Connection,
+    placements: DecalPlacements,
+) -> Result<impl Reply, Rejection> {
+    for decal in &placements.decals {
+        decal.validate()?;
+    }
+
+    let res = match db
+        .save_decal_placements(placements.id.0, placements.decals)
+        .await
+    {
+        Ok(res) => res,
+        Err(e) => {
+            eprintln!("Error {}", e);
+            return Err(warp::reject::not_found());
+        }
+    };
+    Ok(warp::reply::json(&res))
+}
+
 pub async fn post_new_user(db: db::Connection, user: User) -> Result<impl Reply, Rejection> {
     let user = NewUser {
         email: user.email,
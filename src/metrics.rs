@@ -0,0 +1,62 @@
+//! Process-wide counters, exposed in the Prometheus text format at `GET /metrics`.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Base frames served from the frame cache without a request.
+pub static FRAME_CACHE_HITS: AtomicU64 = AtomicU64::new(0);
+/// Base frames the frame cache did not have and downloaded.
+pub static FRAME_CACHE_MISSES: AtomicU64 = AtomicU64::new(0);
+/// Stale cached frames the origin confirmed with `304 Not Modified`.
+pub static FRAME_CACHE_REVALIDATIONS: AtomicU64 = AtomicU64::new(0);
+/// Cached frames removed to stay within the size limit.
+pub static FRAME_CACHE_EVICTIONS: AtomicU64 = AtomicU64::new(0);
+/// Bytes currently held by the frame cache.
+pub static FRAME_CACHE_BYTES: AtomicU64 = AtomicU64::new(0);
+
+pub fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn render() -> String {
+    let metrics: [(&str, &str, &str, &AtomicU64); 5] = [
+        (
+            "carcaro_frame_cache_hits_total",
+            "counter",
+            "Base frames served from the frame cache without a request",
+            &FRAME_CACHE_HITS,
+        ),
+        (
+            "carcaro_frame_cache_misses_total",
+            "counter",
+            "Base frames downloaded because they were not cached",
+            &FRAME_CACHE_MISSES,
+        ),
+        (
+            "carcaro_frame_cache_revalidations_total",
+            "counter",
+            "Stale cached frames confirmed unchanged by the origin",
+            &FRAME_CACHE_REVALIDATIONS,
+        ),
+        (
+            "carcaro_frame_cache_evictions_total",
+            "counter",
+            "Cached frames evicted to stay within the size limit",
+            &FRAME_CACHE_EVICTIONS,
+        ),
+        (
+            "carcaro_frame_cache_bytes",
+            "gauge",
+            "Bytes held by the frame cache",
+            &FRAME_CACHE_BYTES,
+        ),
+    ];
+
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = writeln!(out, "# HELP {} {}", name, help);
+        let _ = writeln!(out, "# TYPE {} {}", name, kind);
+        let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+    }
+    out
+}
@@ -0,0 +1,241 @@
+use super::{ByteRange, ByteStream, ObjectInfo, StoredObject};
+use crate::handle_errors::StorageError;
+use azure_core::auth::Secret;
+use azure_core::request_options::IfMatchCondition;
+use azure_storage::prelude::*;
+use azure_storage::shared_access_signature::service_sas::BlobSharedAccessSignature;
+use azure_storage_blobs::prelude::*;
+use futures::{StreamExt, TryStreamExt};
+use time::{Duration, OffsetDateTime};
+
+/// Azurite's well-known development account, see
+/// https://learn.microsoft.com/azure/storage/common/storage-use-azurite
+pub const EMULATOR_ACCOUNT: &str = "devstoreaccount1";
+pub const EMULATOR_ACCOUNT_KEY: &str =
+    "Eby8vdM02xNOcqFlqUwJPLlmEtlCDXJ1OUzFT50uSRZ6IFsuFq2UVErCz4I6tq/K1SZFPTOtr/KBHBeksoGMGw==";
+/// Blob endpoint of an Azurite running locally with default ports.
+pub const EMULATOR_ENDPOINT: &str = "http://127.0.0.1:10000/devstoreaccount1";
+
+#[derive(Clone)]
+pub struct AzureStorage {
+    account: String,
+    access_key: String,
+    client: ClientBuilder,
+    /// Same client without the SDK's retries. Uploads are retried by
+    /// `container_generation`, with backoff across the whole render, so the
+    /// SDK must not retry them on its own too.
+    upload_client: ClientBuilder,
+}
+
+impl AzureStorage {
+    /// Client for `account`, on the public cloud unless `endpoint` is given.
+    pub fn new(account: &str, access_key: &str, endpoint: Option<&str>) -> AzureStorage {
+        let storage_credentials = StorageCredentials::access_key(account, access_key.to_string());
+        let client = match endpoint {
+            Some(uri) => ClientBuilder::with_location(
+                CloudLocation::Custom {
+                    account: account.to_string(),
+                    uri: uri.trim_end_matches('/').to_string(),
+                },
+                storage_credentials,
+            ),
+            None => ClientBuilder::new(account, storage_credentials),
+        };
+        AzureStorage {
+            account: account.to_string(),
+            access_key: access_key.to_string(),
+            upload_client: client.clone().retry(azure_core::RetryOptions::none()),
+            client,
+        }
+    }
+
+    /// Client for a local Azurite, at `endpoint` or its default address.
+    pub fn emulator(endpoint: Option<&str>) -> AzureStorage {
+        AzureStorage::new(
+            EMULATOR_ACCOUNT,
+            EMULATOR_ACCOUNT_KEY,
+            Some(endpoint.unwrap_or(EMULATOR_ENDPOINT)),
+        )
+    }
+
+    pub async fn ensure_container(
+        &self,
+        container: &str,
+        public: bool,
+    ) -> Result<(), StorageError> {
+        let container_client = self.client.clone().container_client(container);
+        if !container_client.exists().await? {
+            let create = container_client.create();
+            if public {
+                create.public_access(PublicAccess::Blob).await?;
+            } else {
+                create.await?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn put(
+        &self,
+        container: &str,
+        path: &str,
+        content_type: &'static str,
+        data: Vec<u8>,
+    ) -> Result<(), StorageError> {
+        self.upload_client
+            .clone()
+            .blob_client(container, path)
+            .put_block_blob(data)
+            .content_type(content_type)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get(&self, container: &str, path: &str) -> Result<Vec<u8>, StorageError> {
+        Ok(self
+            .client
+            .clone()
+            .blob_client(container, path)
+            .get_content()
+            .await?)
+    }
+
+    pub async fn head(&self, container: &str, path: &str) -> Result<ObjectInfo, StorageError> {
+        let properties = self
+            .client
+            .clone()
+            .blob_client(container, path)
+            .get_properties()
+            .await?
+            .blob
+            .properties;
+        Ok(ObjectInfo {
+            size: properties.content_length,
+            content_type: Some(properties.content_type),
+            etag: Some(properties.etag.to_string()),
+        })
+    }
+
+    /// Streams the blob in the chunks the SDK downloads it in. The first
+    /// chunk is waited for, so a failed request is an error here and not in
+    /// the middle of a response.
+    pub async fn stream(
+        &self,
+        container: &str,
+        path: &str,
+        range: Option<ByteRange>,
+        if_match: Option<&str>,
+    ) -> Result<ByteStream, StorageError> {
+        let mut get = self.client.clone().blob_client(container, path).get();
+        if let Some(range) = range {
+            get = get.range(range.start..range.end + 1);
+        }
+        if let Some(etag) = if_match {
+            get = get.if_match(IfMatchCondition::Match(etag.to_string()));
+        }
+        let mut chunks = get
+            .into_stream()
+            .map_ok(|response| response.data.map_err(StorageError::from))
+            .map_err(StorageError::from)
+            .try_flatten();
+        let first = match chunks.next().await {
+            Some(first) => first?,
+            None => return Ok(Box::pin(futures::stream::empty())),
+        };
+        Ok(Box::pin(
+            futures::stream::once(async move { Ok(first) }).chain(chunks),
+        ))
+    }
+
+    pub async fn delete(&self, container: &str, path: &str) -> Result<(), StorageError> {
+        self.client
+            .clone()
+            .blob_client(container, path)
+            .delete()
+            .await?;
+        Ok(())
+    }
+
+    pub async fn list(
+        &self,
+        container: &str,
+        prefix: &str,
+    ) -> Result<Vec<StoredObject>, StorageError> {
+        let mut objects = Vec::new();
+        let mut pages = self
+            .client
+            .clone()
+            .container_client(container)
+            .list_blobs()
+            .prefix(prefix.to_string())
+            .into_stream();
+        while let Some(page) = pages.next().await {
+            for blob in page?.blobs.blobs() {
+                objects.push(StoredObject {
+                    key: format!("{}/{}", container, blob.name),
+                    size: blob.properties.content_length,
+                    last_modified: blob.properties.last_modified,
+                });
+            }
+        }
+        Ok(objects)
+    }
+
+    pub async fn list_containers(&self) -> Result<Vec<String>, StorageError> {
+        let mut containers = Vec::new();
+        let mut pages = self
+            .client
+            .clone()
+            .blob_service_client()
+            .list_containers()
+            .into_stream();
+        while let Some(page) = pages.next().await {
+            containers.extend(page?.containers.into_iter().map(|container| container.name));
+        }
+        Ok(containers)
+    }
+
+    pub async fn delete_container(&self, container: &str) -> Result<(), StorageError> {
+        self.client
+            .clone()
+            .container_client(container)
+            .delete()
+            .await?;
+        Ok(())
+    }
+
+    /// Read-only SAS URL for one blob, valid for `lifetime`.
+    pub fn signed_url(
+        &self,
+        container: &str,
+        path: &str,
+        lifetime: Duration,
+    ) -> Result<String, StorageError> {
+        let blob_client = self.client.clone().blob_client(container, path);
+        let resource: String = format!("/blob/{}/{}/{}", self.account, container, path);
+        let permissions = BlobSasPermissions {
+            read: true,
+            ..Default::default()
+        };
+        let signed_token = BlobSharedAccessSignature::new(
+            Secret::new(self.access_key.clone()),
+            resource,
+            permissions,
+            OffsetDateTime::now_utc() + lifetime,
+            BlobSignedResource::Blob,
+        );
+        Ok(blob_client
+            .generate_signed_blob_url(&signed_token)?
+            .to_string())
+    }
+
+    /// Unsigned URL of a blob in a public container.
+    pub fn public_url(&self, container: &str, path: &str) -> Result<String, StorageError> {
+        Ok(self
+            .client
+            .clone()
+            .blob_client(container, path)
+            .url()?
+            .to_string())
+    }
+}
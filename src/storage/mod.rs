@@ -0,0 +1,266 @@
+pub mod azure;
+pub mod s3;
+
+use self::s3::S3Storage;
+use crate::config::{StorageBackend, StorageConfig};
+use crate::handle_errors::StorageError;
+use azure::AzureStorage;
+use bytes::Bytes;
+use futures::Stream;
+use std::pin::Pin;
+use time::{Duration, OffsetDateTime};
+
+/// How long URLs signed for stored renders stay valid.
+const SIGNED_URL_LIFETIME: Duration = Duration::hours(1);
+
+/// Object storage renders and uploaded assets are kept in. Objects are
+/// addressed by keys of the form `{container}/{path}`, which is also what the
+/// database stores, so keys stay valid whichever backend holds them.
+#[derive(Clone)]
+pub struct Storage {
+    backend: Backend,
+    container: String,
+}
+
+/// One object found when listing storage.
+#[derive(Debug, Clone)]
+pub struct StoredObject {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: OffsetDateTime,
+}
+
+/// Metadata of one stored object.
+#[derive(Debug, Clone)]
+pub struct ObjectInfo {
+    pub size: u64,
+    pub content_type: Option<String>,
+    /// Entity tag as the backend reports it, quoted.
+    pub etag: Option<String>,
+}
+
+/// Inclusive byte range of an object, like an HTTP `Range`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+impl ByteRange {
+    pub fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Contents of an object as they arrive from the backend.
+pub type ByteStream = Pin<Box<dyn Stream<Item = Result<Bytes, StorageError>> + Send>>;
+
+#[derive(Clone)]
+enum Backend {
+    Azure(AzureStorage),
+    S3(S3Storage),
+}
+
+/// Splits a storage key into its container and the path inside it.
+pub fn split_key(key: &str) -> Result<(&str, &str), StorageError> {
+    match key.split_once('/') {
+        Some((container, path)) if !container.is_empty() && !path.is_empty() => {
+            Ok((container, path))
+        }
+        _ => Err(StorageError::InvalidKey(key.to_string())),
+    }
+}
+
+/// Whether a stored reference is a storage key rather than a full URL.
+pub fn is_key(stored: &str) -> bool {
+    !(stored.starts_with("https://") || stored.starts_with("http://"))
+}
+
+impl Storage {
+    pub fn new(config: &StorageConfig) -> Storage {
+        let backend = match config.backend {
+            StorageBackend::Azure if config.emulator => {
+                Backend::Azure(AzureStorage::emulator(config.endpoint.as_deref()))
+            }
+            StorageBackend::Azure => {
+                let access_key = std::fs::read_to_string(&config.access_key_file)
+                    .expect("Failed to read storage access key");
+                Backend::Azure(AzureStorage::new(
+                    &config.account,
+                    access_key.trim(),
+                    config.endpoint.as_deref(),
+                ))
+            }
+            StorageBackend::S3 => Backend::S3(
+                S3Storage::new(&config.s3, config.endpoint.as_deref())
+                    .expect("Invalid S3 storage settings"),
+            ),
+        };
+        Storage {
+            backend,
+            container: config.container.clone(),
+        }
+    }
+
+    /// The container renders are stored in.
+    pub fn container(&self) -> &str {
+        &self.container
+    }
+
+    pub async fn ensure_container(
+        &self,
+        container: &str,
+        public: bool,
+    ) -> Result<(), StorageError> {
+        match &self.backend {
+            Backend::Azure(azure) => azure.ensure_container(container, public).await,
+            Backend::S3(s3) => s3.ensure_container(container).await,
+        }
+    }
+
+    pub async fn put(
+        &self,
+        key: &str,
+        content_type: &'static str,
+        data: Vec<u8>,
+    ) -> Result<(), StorageError> {
+        let (container, path) = split_key(key)?;
+        match &self.backend {
+            Backend::Azure(azure) => azure.put(container, path, content_type, data).await,
+            Backend::S3(s3) => s3.put(container, path, content_type, data).await,
+        }
+    }
+
+    pub async fn get(&self, key: &str) -> Result<Vec<u8>, StorageError> {
+        let (container, path) = split_key(key)?;
+        match &self.backend {
+            Backend::Azure(azure) => azure.get(container, path).await,
+            Backend::S3(s3) => s3.get(container, path).await,
+        }
+    }
+
+    pub async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        let (container, path) = split_key(key)?;
+        match &self.backend {
+            Backend::Azure(azure) => azure.head(container, path).await,
+            Backend::S3(s3) => s3.head(container, path).await,
+        }
+    }
+
+    /// Contents of an object, or of `range` of it. With `if_match` it fails
+    /// with a precondition error, before any data, when the object no longer
+    /// has that entity tag.
+    pub async fn stream(
+        &self,
+        key: &str,
+        range: Option<ByteRange>,
+        if_match: Option<&str>,
+    ) -> Result<ByteStream, StorageError> {
+        let (container, path) = split_key(key)?;
+        match &self.backend {
+            Backend::Azure(azure) => azure.stream(container, path, range, if_match).await,
+            Backend::S3(s3) => s3.stream(container, path, range, if_match).await,
+        }
+    }
+
+    pub async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let (container, path) = split_key(key)?;
+        match &self.backend {
+            Backend::Azure(azure) => azure.delete(container, path).await,
+            Backend::S3(s3) => s3.delete(container, path).await,
+        }
+    }
+
+    /// Every object in `container` whose path starts with `prefix`.
+    pub async fn list(
+        &self,
+        container: &str,
+        prefix: &str,
+    ) -> Result<Vec<StoredObject>, StorageError> {
+        match &self.backend {
+            Backend::Azure(azure) => azure.list(container, prefix).await,
+            Backend::S3(s3) => s3.list(container, prefix).await,
+        }
+    }
+
+    pub async fn list_containers(&self) -> Result<Vec<String>, StorageError> {
+        match &self.backend {
+            Backend::Azure(azure) => azure.list_containers().await,
+            // Renders on S3 have always shared one bucket, so there are no
+            // containers of the old per-render layout to find
+            Backend::S3(_) => Ok(Vec::new()),
+        }
+    }
+
+    /// Deletes a container together with everything left in it.
+    pub async fn delete_container(&self, container: &str) -> Result<(), StorageError> {
+        match &self.backend {
+            Backend::Azure(azure) => azure.delete_container(container).await,
+            Backend::S3(s3) => s3.delete_container(container).await,
+        }
+    }
+
+    /// Turns a stored frame reference into a URL that can be fetched. Storage
+    /// keys get a short-lived read-only URL; full URLs, such as base images
+    /// hosted elsewhere, are returned as they are.
+    pub fn signed_url(&self, stored: &str) -> String {
+        if !is_key(stored) {
+            return stored.to_string();
+        }
+        let signed = split_key(stored).and_then(|(container, path)| match &self.backend {
+            Backend::Azure(azure) => azure.signed_url(container, path, SIGNED_URL_LIFETIME),
+            Backend::S3(s3) => s3.signed_url(container, path, SIGNED_URL_LIFETIME),
+        });
+        match signed {
+            Ok(url) => url,
+            Err(e) => {
+                eprintln!("Error signing {} {}", stored, e);
+                stored.to_string()
+            }
+        }
+    }
+
+    /// Whether `url` is the public URL of an object directly in `container`,
+    /// named the way uploaded assets are. URLs with a query, of other
+    /// containers or of nested paths are not.
+    pub fn is_public_asset(&self, container: &str, url: &str) -> bool {
+        let url = match reqwest::Url::parse(url) {
+            Ok(url) => url,
+            Err(_) => return false,
+        };
+        if url.query().is_some() || url.fragment().is_some() {
+            return false;
+        }
+        let name = match url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+        {
+            Some(name) => name,
+            None => return false,
+        };
+        let plain_name = !name.is_empty()
+            && !name.starts_with('.')
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.');
+        if !plain_name {
+            return false;
+        }
+        // The URL the asset would have if it was stored there, to compare with
+        match self.public_url(&format!("{}/{}", container, name)) {
+            Ok(expected) => {
+                reqwest::Url::parse(&expected).map_or(false, |expected| expected == url)
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// URL of an object in a public container, for assets clients refer to later.
+    pub fn public_url(&self, key: &str) -> Result<String, StorageError> {
+        let (container, path) = split_key(key)?;
+        match &self.backend {
+            Backend::Azure(azure) => azure.public_url(container, path),
+            Backend::S3(s3) => s3.public_url(container, path),
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use super::{ByteRange, ByteStream, ObjectInfo, StoredObject};
+use crate::config::S3Config;
+use crate::handle_errors::StorageError;
+use futures::StreamExt;
+use s3::bucket::Bucket;
+use s3::creds::Credentials;
+use s3::error::S3Error;
+use s3::{BucketConfiguration, Region};
+use time::format_description::well_known::Rfc3339;
+use time::{Duration, OffsetDateTime};
+
+/// S3 and S3-compatible servers such as MinIO. Containers are buckets.
+#[derive(Clone)]
+pub struct S3Storage {
+    region: Region,
+    credentials: Credentials,
+    /// Custom endpoints are addressed as `{endpoint}/{bucket}`, since servers
+    /// like MinIO usually do not have a DNS name per bucket.
+    path_style: bool,
+}
+
+impl S3Storage {
+    /// Client for AWS in `config.region`, or for the server at `endpoint`.
+    pub fn new(config: &S3Config, endpoint: Option<&str>) -> Result<S3Storage, StorageError> {
+        let region = match endpoint {
+            Some(endpoint) => Region::Custom {
+                region: config.region.clone(),
+                endpoint: endpoint.trim_end_matches('/').to_string(),
+            },
+            None => config.region.parse().map_err(S3Error::from)?,
+        };
+        let credentials = Credentials::new(
+            Some(&config.access_key_id),
+            Some(&config.secret_access_key),
+            None,
+            None,
+            None,
+        )
+        .map_err(S3Error::from)?;
+        Ok(S3Storage {
+            region,
+            credentials,
+            path_style: endpoint.is_some(),
+        })
+    }
+
+    fn bucket(&self, name: &str) -> Result<Bucket, StorageError> {
+        let bucket = Bucket::new(name, self.region.clone(), self.credentials.clone())?;
+        if self.path_style {
+            Ok(bucket.with_path_style())
+        } else {
+            Ok(bucket)
+        }
+    }
+
+    /// Creates the bucket if it is missing. Anonymous read access for public
+    /// buckets is a bucket policy and has to be set up on the server.
+    pub async fn ensure_container(&self, container: &str) -> Result<(), StorageError> {
+        if self.bucket(container)?.exists().await? {
+            return Ok(());
+        }
+        let configuration = BucketConfiguration::default();
+        let response = if self.path_style {
+            Bucket::create_with_path_style(
+                container,
+                self.region.clone(),
+                self.credentials.clone(),
+                configuration,
+            )
+            .await?
+        } else {
+            Bucket::create(
+                container,
+                self.region.clone(),
+                self.credentials.clone(),
+                configuration,
+            )
+            .await?
+        };
+        if !response.success() {
+            return Err(
+                S3Error::HttpFailWithBody(response.response_code, response.response_text).into(),
+            );
+        }
+        Ok(())
+    }
+
+    pub async fn put(
+        &self,
+        container: &str,
+        path: &str,
+        content_type: &'static str,
+        data: Vec<u8>,
+    ) -> Result<(), StorageError> {
+        self.bucket(container)?
+            .put_object_with_content_type(path, &data, content_type)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get(&self, container: &str, path: &str) -> Result<Vec<u8>, StorageError> {
+        let response = self.bucket(container)?.get_object(path).await?;
+        Ok(response.bytes().to_vec())
+    }
+
+    pub async fn head(&self, container: &str, path: &str) -> Result<ObjectInfo, StorageError> {
+        let (head, _) = self.bucket(container)?.head_object(path).await?;
+        Ok(ObjectInfo {
+            size: head.content_length.unwrap_or(0).max(0) as u64,
+            content_type: head.content_type,
+            etag: head.e_tag,
+        })
+    }
+
+    /// Streams the object as the server sends it. The client has no ranged
+    /// or conditional streaming of its own, so both go in as request headers.
+    pub async fn stream(
+        &self,
+        container: &str,
+        path: &str,
+        range: Option<ByteRange>,
+        if_match: Option<&str>,
+    ) -> Result<ByteStream, StorageError> {
+        let mut bucket = self.bucket(container)?;
+        if let Some(range) = range {
+            bucket.add_header("Range", &format!("bytes={}-{}", range.start, range.end));
+        }
+        if let Some(etag) = if_match {
+            bucket.add_header("If-Match", etag);
+        }
+        let response = bucket.get_object_stream(path).await?;
+        if !(200..300).contains(&response.status_code) {
+            return Err(S3Error::HttpFailWithBody(response.status_code, String::new()).into());
+        }
+        Ok(Box::pin(response.bytes.map(Ok)))
+    }
+
+    pub async fn delete(&self, container: &str, path: &str) -> Result<(), StorageError> {
+        self.bucket(container)?.delete_object(path).await?;
+        Ok(())
+    }
+
+    pub async fn list(
+        &self,
+        container: &str,
+        prefix: &str,
+    ) -> Result<Vec<StoredObject>, StorageError> {
+        let pages = self
+            .bucket(container)?
+            .list(prefix.to_string(), None)
+            .await?;
+        Ok(pages
+            .into_iter()
+            .flat_map(|page| page.contents)
+            .map(|object| StoredObject {
+                key: format!("{}/{}", container, object.key),
+                size: object.size,
+                // An object of unknown age counts as new, so it is never
+                // taken for an old orphan
+                last_modified: OffsetDateTime::parse(&object.last_modified, &Rfc3339)
+                    .unwrap_or_else(|_| OffsetDateTime::now_utc()),
+            })
+            .collect())
+    }
+
+    pub async fn delete_container(&self, container: &str) -> Result<(), StorageError> {
+        self.bucket(container)?.delete().await?;
+        Ok(())
+    }
+
+    /// Presigned GET URL for one object, valid for `lifetime`.
+    pub fn signed_url(
+        &self,
+        container: &str,
+        path: &str,
+        lifetime: Duration,
+    ) -> Result<String, StorageError> {
+        Ok(self
+            .bucket(container)?
+            .presign_get(path, lifetime.whole_seconds() as u32, None)?)
+    }
+
+    /// Unsigned URL of an object in a bucket that allows anonymous reads.
+    pub fn public_url(&self, container: &str, path: &str) -> Result<String, StorageError> {
+        Ok(format!("{}/{}", self.bucket(container)?.url(), path))
+    }
+}
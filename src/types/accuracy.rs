@@ -0,0 +1,39 @@
+use crate::types::image::ImageId;
+use serde::{Deserialize, Serialize};
+
+/// Mean CIEDE2000 above which a frame is flagged. Shading keeps every real
+/// render some distance from the flat target, so this is well above the
+/// usual "just noticeable" difference of about 2.
+pub const POOR_MEAN_DELTA_E: f64 = 10.0;
+
+/// How close the painted pixels of one rendered frame are to the requested
+/// finish, as CIEDE2000 differences.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct FrameAccuracy {
+    pub frame: usize,
+    pub mean: f64,
+    pub median: f64,
+    pub p95: f64,
+    /// Number of sampled pixels the statistics were computed over.
+    pub pixels: usize,
+    pub poor: bool,
+}
+
+/// Response of `GET /images/{id}/accuracy`.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct AccuracyReport {
+    pub imageid: ImageId,
+    pub frames: Vec<FrameAccuracy>,
+    pub poor: bool,
+}
+
+impl AccuracyReport {
+    pub fn new(imageid: ImageId, frames: Vec<FrameAccuracy>) -> Self {
+        let poor = frames.iter().any(|frame| frame.poor);
+        AccuracyReport {
+            imageid,
+            frames,
+            poor,
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use crate::handle_errors::Error;
+use crate::types::image::ImageId;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_ANIMATION_WIDTH: u32 = 1280;
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimationFormat {
+    #[default]
+    Gif,
+    Apng,
+    Webp,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "png",
+            AnimationFormat::Webp => "webp",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "image/gif",
+            AnimationFormat::Apng => "image/apng",
+            AnimationFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnimationFormat::Gif => "gif",
+            AnimationFormat::Apng => "apng",
+            AnimationFormat::Webp => "webp",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AnimationFormat> {
+        match name {
+            "gif" => Some(AnimationFormat::Gif),
+            "apng" => Some(AnimationFormat::Apng),
+            "webp" => Some(AnimationFormat::Webp),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for exporting a render as a looping 360° animation. `delay_ms` is
+/// how long each frame is shown.
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct AnimationRequest {
+    #[serde(default)]
+    pub format: AnimationFormat,
+    #[serde(default = "default_delay")]
+    pub delay_ms: u32,
+    #[serde(default = "default_width")]
+    pub width: u32,
+}
+
+fn default_delay() -> u32 {
+    100
+}
+
+fn default_width() -> u32 {
+    480
+}
+
+impl AnimationRequest {
+    pub fn validate(&self) -> Result<(), Error> {
+        let delay_valid = (10..=10_000).contains(&self.delay_ms);
+        let width_valid = (1..=MAX_ANIMATION_WIDTH).contains(&self.width);
+        if !delay_valid || !width_valid {
+            return Err(Error::InvalidAnimation);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct Animation {
+    pub id: i32,
+    pub imageid: ImageId,
+    pub format: AnimationFormat,
+    /// A storage key under the image's render prefix. Animations from before
+    /// that are full URLs in the public `animations` container.
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub delay_ms: u32,
+}
+
+impl Animation {
+    /// Replaces the stored reference with `sign(reference)`, see `Image::with_signed_urls`.
+    pub fn with_signed_url<F: Fn(&str) -> String>(mut self, sign: F) -> Self {
+        self.url = sign(&self.url);
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NewAnimation {
+    pub imageid: ImageId,
+    pub format: AnimationFormat,
+    pub url: String,
+    pub width: u32,
+    pub height: u32,
+    pub delay_ms: u32,
+}
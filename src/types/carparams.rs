@@ -8,7 +8,7 @@ pub struct CarParams {
     pub year: i32,
 }
 
-pub(crate) fn extract_car_params(params: HashMap<String, String>) -> Result<CarParams, Error> {
+pub fn extract_car_params(params: HashMap<String, String>) -> Result<CarParams, Error> {
     if params.contains_key("make") && params.contains_key("model") && params.contains_key("year") {
         return Ok(CarParams {
             make: params.get("make").unwrap().to_string(),
@@ -6,3 +6,29 @@ pub struct Color {
     pub color_name: String,
     pub hex: String,
 }
+
+impl Color {
+    /// The color as RGB, `None` if the stored hex code is malformed.
+    pub fn rgb(&self) -> Option<[u8; 3]> {
+        parse_hex(&self.hex)
+    }
+
+    /// The numeric part of the RAL code, which is stored both as `RAL 3020` and as `3020`.
+    pub fn ral_number(&self) -> Option<u32> {
+        let code = self.ral.trim().to_uppercase();
+        code.trim_start_matches("RAL").trim().parse().ok()
+    }
+}
+
+/// Parses `RRGGBB`, with or without a leading `#`.
+pub fn parse_hex(hex: &str) -> Option<[u8; 3]> {
+    let hex = hex.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let mut rgb = [0u8; 3];
+    for (i, channel) in rgb.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(rgb)
+}
@@ -0,0 +1,146 @@
+use crate::handle_errors::Error;
+use crate::types::accuracy::FrameAccuracy;
+use crate::types::color::{parse_hex, Color};
+use crate::types::image::ImageId;
+use crate::types::output::OutputOptions;
+use serde::{Deserialize, Serialize};
+
+/// Most colors a palette filter may list.
+pub const MAX_PALETTE_COLORS: usize = 100;
+
+/// Which colors a color wall is rendered in: every row of the color table,
+/// the RAL codes from `from` to `to` inclusive, or a palette of up to
+/// `MAX_PALETTE_COLORS` hex codes.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ColorFilter {
+    All,
+    RalRange { from: u32, to: u32 },
+    Palette { hex: Vec<String> },
+}
+
+impl ColorFilter {
+    /// The colors of the table matching the filter. Palette colors that are not
+    /// in the table are kept with their hex code as name.
+    pub fn select(&self, catalog: Vec<Color>) -> Result<Vec<WallColor>, Error> {
+        let colors: Vec<WallColor> = match self {
+            ColorFilter::All => catalog
+                .into_iter()
+                .filter_map(WallColor::from_catalog)
+                .collect(),
+            ColorFilter::RalRange { from, to } => catalog
+                .into_iter()
+                .filter(|color| {
+                    color
+                        .ral_number()
+                        .map_or(false, |number| (*from..=*to).contains(&number))
+                })
+                .filter_map(WallColor::from_catalog)
+                .collect(),
+            ColorFilter::Palette { hex } => {
+                if hex.len() > MAX_PALETTE_COLORS {
+                    return Err(Error::TooManyColors);
+                }
+                let mut colors = Vec::new();
+                for code in hex {
+                    let rgb = parse_hex(code).ok_or(Error::InvalidColorWall)?;
+                    let known = catalog.iter().find(|color| color.rgb() == Some(rgb));
+                    colors.push(WallColor {
+                        ral: known.map(|color| color.ral.clone()),
+                        color_name: known.map(|color| color.color_name.clone()),
+                        colors: rgb,
+                    });
+                }
+                colors
+            }
+        };
+        if colors.is_empty() {
+            return Err(Error::InvalidColorWall);
+        }
+        Ok(colors)
+    }
+}
+
+/// Body of `POST /images/{id}/colorwalls`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ColorWallRequest {
+    pub filter: ColorFilter,
+    pub userid: Option<i32>,
+    #[serde(default)]
+    pub output: OutputOptions,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct WallColor {
+    pub ral: Option<String>,
+    pub color_name: Option<String>,
+    pub colors: [u8; 3],
+}
+
+impl WallColor {
+    fn from_catalog(color: Color) -> Option<WallColor> {
+        Some(WallColor {
+            colors: color.rgb()?,
+            ral: Some(color.ral),
+            color_name: Some(color.color_name),
+        })
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorWallStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+impl ColorWallStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorWallStatus::Running => "running",
+            ColorWallStatus::Done => "done",
+            ColorWallStatus::Failed => "failed",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ColorWallStatus> {
+        match name {
+            "running" => Some(ColorWallStatus::Running),
+            "done" => Some(ColorWallStatus::Done),
+            "failed" => Some(ColorWallStatus::Failed),
+            _ => None,
+        }
+    }
+}
+
+/// One color of a color wall in the job's index: the render it produced, or
+/// why it could not be rendered.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ColorWallEntry {
+    pub color: WallColor,
+    pub imageid: Option<ImageId>,
+    pub url: Vec<String>,
+    pub accuracy: Vec<FrameAccuracy>,
+    pub error: Option<String>,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ColorWall {
+    pub id: i32,
+    pub imageid: ImageId,
+    pub status: ColorWallStatus,
+    pub total: i32,
+    pub completed: i32,
+    pub renders: Vec<ColorWallEntry>,
+}
+
+impl ColorWall {
+    /// Signs the frame references of every render in the index, see `Image::with_signed_urls`.
+    pub fn with_signed_urls<F: Fn(&str) -> String>(mut self, sign: F) -> Self {
+        for entry in &mut self.renders {
+            entry.url = entry.url.iter().map(|url| sign(url)).collect();
+        }
+        self
+    }
+}
@@ -0,0 +1,41 @@
+use crate::handle_errors::Error;
+use crate::types::preview::PreviewFormat;
+use serde::{Deserialize, Serialize};
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ComparisonLayout {
+    #[default]
+    SideBySide,
+    Diagonal,
+    Slider,
+}
+
+/// Query of `GET /images/{id}/compare`. `split` is where the slider sits,
+/// relative to the frame width.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ComparisonQuery {
+    #[serde(default)]
+    pub frame: usize,
+    #[serde(default)]
+    pub layout: ComparisonLayout,
+    #[serde(default = "default_split")]
+    pub split: f64,
+    #[serde(default)]
+    pub captions: bool,
+    #[serde(default)]
+    pub format: PreviewFormat,
+}
+
+fn default_split() -> f64 {
+    0.5
+}
+
+impl ComparisonQuery {
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&self.split) {
+            return Err(Error::InvalidComparison);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use crate::handle_errors::Error;
+use crate::types::image::ImageId;
+use serde::{Deserialize, Serialize};
+
+/// Where a decal goes on one frame. `x` and `y` are the relative position of
+/// the decal's center, `scale` is its width relative to the frame width and
+/// `rotation` is clockwise in degrees.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct DecalPlacement {
+    pub frame: usize,
+    pub url: String,
+    pub x: f64,
+    pub y: f64,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub rotation: f64,
+}
+
+fn default_scale() -> f64 {
+    0.2
+}
+
+impl DecalPlacement {
+    pub fn validate(&self) -> Result<(), Error> {
+        let in_frame = (0.0..=1.0).contains(&self.x) && (0.0..=1.0).contains(&self.y);
+        if self.url.is_empty()
+            || !in_frame
+            || !self.scale.is_finite()
+            || self.scale <= 0.0
+            || !self.rotation.is_finite()
+        {
+            return Err(Error::InvalidDecal);
+        }
+        Ok(())
+    }
+}
+
+/// Decal placements saved for a base image set so they can be reused by later renders.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DecalPlacements {
+    pub id: ImageId,
+    pub decals: Vec<DecalPlacement>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadedDecal {
+    pub url: String,
+}
@@ -0,0 +1,34 @@
+use crate::handle_errors::Error;
+use crate::types::gradient::Gradient;
+use crate::types::texture::Texture;
+
+/// What the masked paint area of each frame gets recolored with.
+#[derive(PartialEq, Debug, Clone)]
+pub enum Finish {
+    Solid([u8; 3]),
+    Gradient(Gradient),
+    Texture(Texture),
+}
+
+impl Finish {
+    /// Picks the finish of a request: a gradient or a texture when one is given,
+    /// otherwise the solid color.
+    pub fn from_request(
+        colors: [u8; 3],
+        gradient: &Option<Gradient>,
+        texture: &Option<Texture>,
+    ) -> Result<Finish, Error> {
+        match (gradient, texture) {
+            (Some(_), Some(_)) => Err(Error::ConflictingFinish),
+            (Some(gradient), None) => {
+                gradient.validate()?;
+                Ok(Finish::Gradient(gradient.clone().sorted()))
+            }
+            (None, Some(texture)) => {
+                texture.validate()?;
+                Ok(Finish::Texture(texture.clone()))
+            }
+            (None, None) => Ok(Finish::Solid(colors)),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::storage::ByteRange;
+use crate::types::output::VariantSize;
+use serde::{Deserialize, Serialize};
+
+/// Query of `GET /images/{id}/frames/{n}`. Without a size the frame is served
+/// as it is referenced in `image.url`, the largest size that was rendered.
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FrameQuery {
+    #[serde(default)]
+    pub size: Option<VariantSize>,
+}
+
+/// A frame of a base set: its reference as stored in `image.url` and the URL
+/// it is downloaded from. The reference comes from the database, so it is
+/// what the frame cache keys on; the URL may differ with every signing.
+#[derive(Eq, PartialEq, Debug, Clone)]
+pub struct BaseFrame {
+    pub reference: String,
+    pub url: String,
+}
+
+/// What a `Range` header asks for from an object of a given size.
+#[derive(Eq, PartialEq, Debug, Clone, Copy)]
+pub enum RangeRequest {
+    Full,
+    Partial(ByteRange),
+    Unsatisfiable,
+}
+
+impl RangeRequest {
+    /// Reads a single `bytes=` range, `start-end`, `start-` or `-suffix`.
+    /// Anything else, including several ranges, is answered with the whole
+    /// object, which HTTP allows.
+    pub fn parse(header: Option<&str>, size: u64) -> RangeRequest {
+        let spec = match header.and_then(|header| header.trim().strip_prefix("bytes=")) {
+            Some(spec) if !spec.contains(',') => spec.trim(),
+            _ => return RangeRequest::Full,
+        };
+        let (start, end) = match spec.split_once('-') {
+            Some(bounds) => bounds,
+            None => return RangeRequest::Full,
+        };
+        let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(start), Ok(end)) if start <= end => (start, end.min(size.saturating_sub(1))),
+            (Ok(start), Err(_)) if end.is_empty() => (start, size.saturating_sub(1)),
+            (Err(_), Ok(suffix)) if start.is_empty() => {
+                if suffix == 0 {
+                    return RangeRequest::Unsatisfiable;
+                }
+                (size.saturating_sub(suffix), size.saturating_sub(1))
+            }
+            _ => return RangeRequest::Full,
+        };
+        if size == 0 || range.0 >= size {
+            return RangeRequest::Unsatisfiable;
+        }
+        RangeRequest::Partial(ByteRange {
+            start: range.0,
+            end: range.1,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn partial(start: u64, end: u64) -> RangeRequest {
+        RangeRequest::Partial(ByteRange { start, end })
+    }
+
+    #[test]
+    fn reads_single_ranges() {
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=0-499"), 1000),
+            partial(0, 499)
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=500-"), 1000),
+            partial(500, 999)
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=900-5000"), 1000),
+            partial(900, 999)
+        );
+        assert_eq!(RangeRequest::parse(Some("bytes=7-7"), 1000), partial(7, 7));
+    }
+
+    #[test]
+    fn reads_suffix_ranges() {
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=-200"), 1000),
+            partial(800, 999)
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=-5000"), 1000),
+            partial(0, 999)
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=-0"), 1000),
+            RangeRequest::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn serves_the_whole_object_for_other_headers() {
+        assert_eq!(RangeRequest::parse(None, 1000), RangeRequest::Full);
+        assert_eq!(
+            RangeRequest::parse(Some("items=0-1"), 1000),
+            RangeRequest::Full
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=0-1,4-5"), 1000),
+            RangeRequest::Full
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=5-2"), 1000),
+            RangeRequest::Full
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=a-b"), 1000),
+            RangeRequest::Full
+        );
+    }
+
+    #[test]
+    fn rejects_ranges_past_the_end() {
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=1000-"), 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=0-"), 0),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            RangeRequest::parse(Some("bytes=-10"), 0),
+            RangeRequest::Unsatisfiable
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use crate::handle_errors::Error;
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum GradientKind {
+    Linear,
+    Radial,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ColorStop {
+    pub offset: f64,
+    pub color: [u8; 3],
+}
+
+/// A gradient over the painted area. `angle` is the direction of a linear
+/// gradient in degrees (0 runs left to right, 90 top to bottom) and `center`
+/// is the relative position a radial gradient starts from.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct Gradient {
+    pub kind: GradientKind,
+    #[serde(default)]
+    pub angle: f64,
+    #[serde(default = "default_center")]
+    pub center: [f64; 2],
+    pub stops: Vec<ColorStop>,
+}
+
+fn default_center() -> [f64; 2] {
+    [0.5, 0.5]
+}
+
+impl Gradient {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.stops.len() < 2 {
+            return Err(Error::InvalidGradient);
+        }
+        let offsets_valid = self
+            .stops
+            .iter()
+            .all(|stop| stop.offset.is_finite() && (0.0..=1.0).contains(&stop.offset));
+        let center_valid = self.center.iter().all(|c| c.is_finite());
+        if !offsets_valid || !self.angle.is_finite() || !center_valid {
+            return Err(Error::InvalidGradient);
+        }
+        Ok(())
+    }
+
+    pub fn sorted(mut self) -> Self {
+        self.stops
+            .sort_by(|a, b| a.offset.partial_cmp(&b.offset).unwrap());
+        self
+    }
+
+    /// Position of a pixel along the gradient, from 0 to 1, for a pixel at
+    /// `(x, y)` inside an area of `width` by `height` pixels.
+    pub fn position(&self, x: i32, y: i32, width: i32, height: i32) -> f64 {
+        let width = width.max(1) as f64;
+        let height = height.max(1) as f64;
+        let (x, y) = (x as f64, y as f64);
+
+        match self.kind {
+            GradientKind::Linear => {
+                let (sin, cos) = self.angle.to_radians().sin_cos();
+                let u = x / width - 0.5;
+                let v = y / height - 0.5;
+                let extent = 0.5 * (cos.abs() + sin.abs());
+                ((u * cos + v * sin + extent) / (2.0 * extent)).clamp(0.0, 1.0)
+            }
+            GradientKind::Radial => {
+                let cx = self.center[0] * width;
+                let cy = self.center[1] * height;
+                let radius = [(0.0, 0.0), (width, 0.0), (0.0, height), (width, height)]
+                    .iter()
+                    .map(|(px, py)| ((px - cx).powi(2) + (py - cy).powi(2)).sqrt())
+                    .fold(1.0, f64::max);
+                (((x - cx).powi(2) + (y - cy).powi(2)).sqrt() / radius).clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    /// Color at position `t`, interpolated between the surrounding stops.
+    /// Expects the stops to be sorted by offset.
+    pub fn color_at(&self, t: f64) -> [u8; 3] {
+        let first = &self.stops[0];
+        let last = &self.stops[self.stops.len() - 1];
+        if t <= first.offset {
+            return first.color;
+        }
+        if t >= last.offset {
+            return last.color;
+        }
+
+        let upper = self
+            .stops
+            .iter()
+            .position(|stop| stop.offset >= t)
+            .unwrap_or(self.stops.len() - 1);
+        let from = &self.stops[upper - 1];
+        let to = &self.stops[upper];
+        let span = to.offset - from.offset;
+        let f = if span > 0.0 { (t - from.offset) / span } else { 1.0 };
+
+        let mut color = [0u8; 3];
+        for c in 0..3 {
+            let a = from.color[c] as f64;
+            let b = to.color[c] as f64;
+            color[c] = (a + (b - a) * f).round() as u8;
+        }
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(kind: GradientKind, stops: &[(f64, [u8; 3])]) -> Gradient {
+        Gradient {
+            kind,
+            angle: 0.0,
+            center: default_center(),
+            stops: stops
+                .iter()
+                .map(|&(offset, color)| ColorStop { offset, color })
+                .collect(),
+        }
+    }
+
+    fn black_to_white(kind: GradientKind) -> Gradient {
+        gradient(kind, &[(0.0, [0, 0, 0]), (1.0, [255, 255, 255])])
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn interpolates_between_stops() {
+        let gradient = gradient(
+            GradientKind::Linear,
+            &[
+                (0.0, [0, 0, 0]),
+                (0.5, [200, 100, 0]),
+                (1.0, [200, 100, 250]),
+            ],
+        );
+        assert_eq!(gradient.color_at(0.25), [100, 50, 0]);
+        assert_eq!(gradient.color_at(0.5), [200, 100, 0]);
+        assert_eq!(gradient.color_at(0.75), [200, 100, 125]);
+    }
+
+    #[test]
+    fn clamps_outside_the_stops() {
+        let gradient = gradient(
+            GradientKind::Linear,
+            &[(0.2, [10, 20, 30]), (0.8, [40, 50, 60])],
+        );
+        assert_eq!(gradient.color_at(0.0), [10, 20, 30]);
+        assert_eq!(gradient.color_at(0.2), [10, 20, 30]);
+        assert_eq!(gradient.color_at(0.8), [40, 50, 60]);
+        assert_eq!(gradient.color_at(1.0), [40, 50, 60]);
+        assert_eq!(gradient.color_at(0.5), [25, 35, 45]);
+    }
+
+    #[test]
+    fn linear_runs_left_to_right_at_0_degrees() {
+        let gradient = black_to_white(GradientKind::Linear);
+        assert!(close(gradient.position(0, 50, 100, 100), 0.0));
+        assert!(close(gradient.position(50, 0, 100, 100), 0.5));
+        assert!(close(gradient.position(100, 50, 100, 100), 1.0));
+        // Clamped for pixels outside the area
+        assert!(close(gradient.position(150, 50, 100, 100), 1.0));
+    }
+
+    #[test]
+    fn linear_runs_top_to_bottom_at_90_degrees() {
+        let mut gradient = black_to_white(GradientKind::Linear);
+        gradient.angle = 90.0;
+        for x in [0, 40, 100] {
+            assert!(close(gradient.position(x, 0, 100, 80), 0.0));
+            assert!(close(gradient.position(x, 40, 100, 80), 0.5));
+            assert!(close(gradient.position(x, 80, 100, 80), 1.0));
+        }
+    }
+
+    #[test]
+    fn radial_starts_at_the_center() {
+        let mut gradient = black_to_white(GradientKind::Radial);
+        assert!(close(gradient.position(50, 50, 100, 100), 0.0));
+        assert!(close(gradient.position(100, 100, 100, 100), 1.0));
+        assert!(close(gradient.position(0, 0, 100, 100), 1.0));
+
+        // Off center the farthest corner is the end of the gradient
+        gradient.center = [0.0, 0.0];
+        assert!(close(gradient.position(0, 0, 100, 100), 0.0));
+        assert!(close(gradient.position(100, 100, 100, 100), 1.0));
+        assert!(close(gradient.position(50, 50, 100, 100), 0.5));
+    }
+
+    #[test]
+    fn rejects_centers_that_are_not_finite() {
+        let mut gradient = black_to_white(GradientKind::Radial);
+        assert!(gradient.validate().is_ok());
+        let centers = [[f64::NAN, 0.5], [0.5, f64::INFINITY], [f64::NEG_INFINITY, 0.5]];
+        for center in centers {
+            gradient.center = center;
+            assert!(matches!(gradient.validate(), Err(Error::InvalidGradient)));
+        }
+    }
+}
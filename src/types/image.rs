@@ -1,14 +1,26 @@
+use crate::types::accuracy::FrameAccuracy;
+use crate::types::output::{FrameVariants, SpriteSheet, VariantSize};
 use serde::{Deserialize, Serialize};
 
 #[derive(Eq, Hash, PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct ImageId(pub i32);
 
-#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
 pub struct Image {
     pub id: ImageId,
+    /// One reference per frame: a storage key for renders, a full URL for base
+    /// sets hosted elsewhere.
     pub url: Vec<String>,
     pub colors: [u8; 3],
     pub userid: Option<i32>,
+    pub frame_count: i32,
+    /// The base image set a render was made from, `None` for base sets themselves.
+    pub base_imageid: Option<i32>,
+    pub variants: Vec<FrameVariants>,
+    pub sprite_sheet: Option<SpriteSheet>,
+    /// Per-frame color accuracy of a render, empty for base sets and texture finishes.
+    #[serde(default)]
+    pub accuracy: Vec<FrameAccuracy>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -16,4 +28,57 @@ pub struct NewImage {
     pub url: Vec<String>,
     pub colors: [u8; 3],
     pub userid: Option<i32>,
+    pub frame_count: i32,
+    /// The base image set a render was made from, `None` for base sets themselves.
+    pub base_imageid: Option<i32>,
+    pub variants: Vec<FrameVariants>,
+    pub sprite_sheet: Option<SpriteSheet>,
+    /// Per-frame color accuracy of a render, empty for base sets and texture finishes.
+    #[serde(default)]
+    pub accuracy: Vec<FrameAccuracy>,
+}
+
+impl Image {
+    /// Replaces every stored frame reference with `sign(reference)`. Renders
+    /// are stored as storage keys, which are only signed when handed out.
+    pub fn with_signed_urls<F: Fn(&str) -> String>(mut self, sign: F) -> Self {
+        self.url = self.url.iter().map(|url| sign(url)).collect();
+        for frame in &mut self.variants {
+            for variant in &mut frame.variants {
+                variant.url = sign(&variant.url);
+            }
+        }
+        if let Some(sprite_sheet) = &mut self.sprite_sheet {
+            sprite_sheet.url = sign(&sprite_sheet.url);
+        }
+        self
+    }
+
+    /// Stored reference of one frame, in a given size or as listed in `url`.
+    pub fn frame_reference(&self, frame: usize, size: Option<VariantSize>) -> Option<&str> {
+        match size {
+            None => self.url.get(frame).map(|url| url.as_str()),
+            Some(size) => self
+                .variants
+                .iter()
+                .find(|variants| variants.frame == frame)?
+                .variants
+                .iter()
+                .find(|variant| variant.size == size)
+                .map(|variant| variant.url.as_str()),
+        }
+    }
+
+    /// Every stored reference of the image: frames, their size variants and
+    /// the sprite sheet.
+    pub fn references(&self) -> Vec<&str> {
+        let mut references: Vec<&str> = self.url.iter().map(|url| url.as_str()).collect();
+        for frame in &self.variants {
+            references.extend(frame.variants.iter().map(|variant| variant.url.as_str()));
+        }
+        if let Some(sprite_sheet) = &self.sprite_sheet {
+            references.push(&sprite_sheet.url);
+        }
+        references
+    }
 }
@@ -1,8 +1,60 @@
-use crate::types::image::Image;
+use crate::handle_errors::Error;
+use crate::types::decal::DecalPlacement;
+use crate::types::finish::Finish;
+use crate::types::gradient::Gradient;
+use crate::types::image::{Image, ImageId};
+use crate::types::output::OutputOptions;
+use crate::types::texture::Texture;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ImageRequest {
     pub url: Vec<String>,
     pub colors: [u8; 3],
+    pub frame_count: i32,
+}
+
+impl ImageRequest {
+    /// The base frame URLs, checked against the frame count stored with the image set.
+    pub fn frame_urls(self) -> Result<Vec<String>, Error> {
+        if self.frame_count <= 0 || self.url.len() != self.frame_count as usize {
+            return Err(Error::FrameCountMismatch);
+        }
+        Ok(self.url)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RenderRequest {
+    pub id: ImageId,
+    pub colors: [u8; 3],
+    pub userid: Option<i32>,
+    #[serde(default)]
+    pub gradient: Option<Gradient>,
+    #[serde(default)]
+    pub texture: Option<Texture>,
+    #[serde(default)]
+    pub decals: Vec<DecalPlacement>,
+    #[serde(default)]
+    pub output: OutputOptions,
+}
+
+impl RenderRequest {
+    pub fn finish(&self) -> Result<Finish, Error> {
+        Finish::from_request(self.colors, &self.gradient, &self.texture)
+    }
+
+    pub fn output(&self) -> Result<OutputOptions, Error> {
+        if !self.output.is_valid() {
+            return Err(Error::InvalidOutput);
+        }
+        Ok(self.output.clone())
+    }
+
+    pub fn decals(&self) -> Result<Vec<DecalPlacement>, Error> {
+        for decal in &self.decals {
+            decal.validate()?;
+        }
+        Ok(self.decals.clone())
+    }
 }
@@ -1,7 +1,20 @@
+pub mod accuracy;
+pub mod animation;
 pub mod car;
 pub mod carparams;
 pub mod color;
+pub mod color_wall;
+pub mod comparison;
+pub mod decal;
+pub mod finish;
+pub mod frame;
+pub mod gradient;
 pub mod image;
 pub mod image_request;
+pub mod output;
+pub mod preflight;
+pub mod preview;
+pub mod reconcile;
+pub mod texture;
 pub mod user;
 pub mod favorite;
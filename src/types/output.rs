@@ -0,0 +1,181 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// What renders were always stored as, so clients that do not ask for a
+    /// format keep getting it.
+    #[default]
+    Png,
+    Webp,
+    Jpeg,
+    Avif,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Avif => "image/avif",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<OutputFormat> {
+        match name {
+            "png" => Some(OutputFormat::Png),
+            "webp" => Some(OutputFormat::Webp),
+            "jpeg" | "jpg" => Some(OutputFormat::Jpeg),
+            "avif" => Some(OutputFormat::Avif),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantSize {
+    Thumbnail,
+    Medium,
+    Full,
+}
+
+impl VariantSize {
+    /// Width the frame is scaled down to, `None` keeps the rendered width.
+    pub fn width(&self) -> Option<u32> {
+        match self {
+            VariantSize::Thumbnail => Some(320),
+            VariantSize::Medium => Some(960),
+            VariantSize::Full => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            VariantSize::Thumbnail => "thumbnail",
+            VariantSize::Medium => "medium",
+            VariantSize::Full => "full",
+        }
+    }
+}
+
+/// How rendered frames are encoded. `quality` goes from 1 to 100 and is ignored for PNG.
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct OutputOptions {
+    #[serde(default)]
+    pub format: OutputFormat,
+    #[serde(default = "default_quality")]
+    pub quality: u8,
+    #[serde(default = "default_sizes", deserialize_with = "unique_sizes")]
+    pub sizes: Vec<VariantSize>,
+    /// Frame size used for the optional sprite sheet, scaled down further if the
+    /// sheet would be larger than 8192 pixels; no sheet is made when unset.
+    #[serde(default)]
+    pub sprite_sheet: Option<VariantSize>,
+}
+
+fn default_quality() -> u8 {
+    80
+}
+
+fn default_sizes() -> Vec<VariantSize> {
+    vec![VariantSize::Thumbnail, VariantSize::Medium, VariantSize::Full]
+}
+
+/// Sizes in the order requested, each once; a repeated size would be
+/// uploaded under the same key twice.
+fn unique_sizes<'de, D>(deserializer: D) -> Result<Vec<VariantSize>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let mut sizes: Vec<VariantSize> = Vec::new();
+    for size in Vec::<VariantSize>::deserialize(deserializer)? {
+        if !sizes.contains(&size) {
+            sizes.push(size);
+        }
+    }
+    Ok(sizes)
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        OutputOptions {
+            format: OutputFormat::default(),
+            quality: default_quality(),
+            sizes: default_sizes(),
+            sprite_sheet: None,
+        }
+    }
+}
+
+impl OutputOptions {
+    pub fn is_valid(&self) -> bool {
+        (1..=100).contains(&self.quality) && !self.sizes.is_empty()
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct Variant {
+    pub size: VariantSize,
+    pub format: OutputFormat,
+    pub width: u32,
+    pub height: u32,
+    pub url: String,
+}
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct FrameVariants {
+    pub frame: usize,
+    pub variants: Vec<Variant>,
+}
+
+impl FrameVariants {
+    /// URL of the largest variant, which is what `Image::url` lists per frame.
+    pub fn largest_url(&self) -> Option<String> {
+        self.variants
+            .iter()
+            .max_by_key(|variant| variant.width)
+            .map(|variant| variant.url.clone())
+    }
+}
+
+/// Where one frame sits in a sprite sheet. `angle` is the viewing angle in
+/// degrees, assuming the frames are spread evenly over a full turn.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct SpriteFrame {
+    pub frame: usize,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub angle: f64,
+}
+
+/// Manifest of a sprite sheet holding every frame of a render in a grid.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct SpriteSheet {
+    pub url: String,
+    pub format: OutputFormat,
+    pub width: u32,
+    pub height: u32,
+    pub columns: u32,
+    pub rows: u32,
+    pub frames: Vec<SpriteFrame>,
+}
+
+/// Everything `upload_frames` stored for a render.
+#[derive(PartialEq, Debug, Clone)]
+pub struct RenderUpload {
+    pub frames: Vec<FrameVariants>,
+    pub sprite_sheet: Option<SpriteSheet>,
+}
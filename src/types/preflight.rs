@@ -0,0 +1,57 @@
+use crate::handle_errors::Error;
+use crate::types::gradient::Gradient;
+use crate::types::image::ImageId;
+use serde::{Deserialize, Serialize};
+
+/// Body of `POST /cars/preflight`: the colors a render would use on an image set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreflightRequest {
+    pub id: ImageId,
+    pub colors: [u8; 3],
+    #[serde(default)]
+    pub gradient: Option<Gradient>,
+}
+
+impl PreflightRequest {
+    /// Every color that has to be reproduced: the solid color, or each stop of the gradient.
+    pub fn targets(&self) -> Result<Vec<[u8; 3]>, Error> {
+        match &self.gradient {
+            Some(gradient) => {
+                gradient.validate()?;
+                Ok(gradient.stops.iter().map(|stop| stop.color).collect())
+            }
+            None => Ok(vec![self.colors]),
+        }
+    }
+}
+
+#[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PreflightWarning {
+    /// Lighter than the paint of the base frames can be pushed.
+    TooLight,
+    /// Darker than the paint of the base frames can be pulled.
+    TooDark,
+    /// Predicted mean CIEDE2000 of the render is above the accuracy threshold.
+    PoorMatch,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct ColorCheck {
+    pub color: [u8; 3],
+    /// HSL lightness in percent of the requested color.
+    pub target_lightness: f64,
+    /// Mean HSL lightness in percent the painted area is expected to end up with.
+    pub predicted_lightness: f64,
+    pub predicted_delta_e: f64,
+    pub warnings: Vec<PreflightWarning>,
+    /// Closest color with the same hue and saturation that the base frames can show.
+    pub suggested: Option<[u8; 3]>,
+}
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct PreflightReport {
+    /// Lowest and highest mean lightness in percent a render of this image set can reach.
+    pub reachable_lightness: [f64; 2],
+    pub colors: Vec<ColorCheck>,
+}
@@ -0,0 +1,74 @@
+use crate::handle_errors::Error;
+use crate::types::decal::DecalPlacement;
+use crate::types::finish::Finish;
+use crate::types::gradient::Gradient;
+use crate::types::image::ImageId;
+use crate::types::texture::Texture;
+use serde::{Deserialize, Serialize};
+
+pub const MAX_PREVIEW_WIDTH: i32 = 1024;
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewFormat {
+    #[default]
+    Webp,
+    Png,
+}
+
+impl PreviewFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            PreviewFormat::Webp => ".webp",
+            PreviewFormat::Png => ".png",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            PreviewFormat::Webp => "image/webp",
+            PreviewFormat::Png => "image/png",
+        }
+    }
+}
+
+/// A single base frame recolored at reduced resolution. `id` is the image set
+/// and `frame` the index of the frame in it, also used to pick the decals
+/// placed on it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreviewRequest {
+    pub id: ImageId,
+    #[serde(default)]
+    pub frame: usize,
+    pub colors: [u8; 3],
+    #[serde(default)]
+    pub gradient: Option<Gradient>,
+    #[serde(default)]
+    pub texture: Option<Texture>,
+    #[serde(default)]
+    pub decals: Vec<DecalPlacement>,
+    #[serde(default = "default_width")]
+    pub width: i32,
+    #[serde(default)]
+    pub format: PreviewFormat,
+}
+
+fn default_width() -> i32 {
+    480
+}
+
+impl PreviewRequest {
+    pub fn finish(&self) -> Result<Finish, Error> {
+        Finish::from_request(self.colors, &self.gradient, &self.texture)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.width <= 0 || self.width > MAX_PREVIEW_WIDTH {
+            return Err(Error::InvalidPreview);
+        }
+        for decal in &self.decals {
+            decal.validate()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+use crate::types::image::ImageId;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone)]
+pub struct ReconcileOptions {
+    /// Only report what would be deleted.
+    pub dry_run: bool,
+    /// Anonymous renders older than this many days are deleted, `None` keeps them.
+    pub anonymous_retention_days: Option<i32>,
+    /// Objects without an image row are only deleted once they are this old, so
+    /// renders that are still uploading, whose row is inserted last, are left alone.
+    pub orphan_grace_hours: i64,
+}
+
+/// An image row that refers to storage objects that do not exist.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MissingBlobs {
+    pub imageid: ImageId,
+    pub keys: Vec<String>,
+    /// Renders are deleted; base sets are only reported.
+    pub removed: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReconcileReport {
+    pub dry_run: bool,
+    /// Objects of renders that have no image row.
+    pub orphaned_objects: Vec<String>,
+    /// Containers of the old container-per-render layout that no image refers to.
+    pub orphaned_containers: Vec<String>,
+    pub missing_blobs: Vec<MissingBlobs>,
+    /// Anonymous renders past their retention.
+    pub expired_images: Vec<ImageId>,
+    /// Objects of removed images, their animations included.
+    pub removed_objects: Vec<String>,
+    pub orphaned_bytes: u64,
+    /// Failures of single deletions and listings; the job carries on past them.
+    pub errors: Vec<String>,
+}
@@ -0,0 +1,40 @@
+use crate::handle_errors::Error;
+use serde::{Deserialize, Serialize};
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureMode {
+    /// Repeat the texture at its own size multiplied by `scale`.
+    #[default]
+    Tile,
+    /// Stretch a single copy of the texture over the painted area.
+    Scale,
+}
+
+/// A wrap texture uploaded through `POST /textures`, referenced by its URL.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct Texture {
+    pub url: String,
+    #[serde(default)]
+    pub mode: TextureMode,
+    #[serde(default = "default_scale")]
+    pub scale: f64,
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+impl Texture {
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.url.is_empty() || !self.scale.is_finite() || self.scale <= 0.0 {
+            return Err(Error::InvalidTexture);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadedTexture {
+    pub url: String,
+}
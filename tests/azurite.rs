@@ -0,0 +1,34 @@
+//! Runs the Azure storage code path against a local Azurite. The tests are
+//! ignored by default; start the emulator and run them with
+//!
+//!     docker run -p 10000:10000 mcr.microsoft.com/azure-storage/azurite azurite-blob --blobHost 0.0.0.0
+//!     cargo test --test azurite -- --ignored
+//!
+//! `CARCARO_STORAGE_ENDPOINT` points them at an emulator somewhere else.
+
+#[path = "common/storage_suite.rs"]
+mod storage_suite;
+
+use carcaro::config::{StorageBackend, StorageConfig};
+use carcaro::storage::Storage;
+
+fn emulator_storage() -> Storage {
+    Storage::new(&StorageConfig {
+        backend: StorageBackend::Azure,
+        container: format!("test-{}", uuid::Uuid::new_v4()),
+        emulator: true,
+        ..StorageConfig::from_env()
+    })
+}
+
+#[tokio::test]
+#[ignore = "needs Azurite"]
+async fn put_get_sign_list_and_delete() {
+    storage_suite::put_get_sign_list_and_delete(emulator_storage()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs Azurite"]
+async fn upload_and_remove_render() {
+    storage_suite::upload_and_remove_render(emulator_storage()).await;
+}
@@ -0,0 +1,35 @@
+//! The per-pixel `colorsys` kernel `apply_color_change` replaced, kept as the
+//! reference the row-slice kernel is checked and benchmarked against.
+
+use carcaro::functionality::recolor::non_linear_transform;
+use colorsys::{Hsl, Rgb};
+use opencv::core::{Mat, MatTrait, MatTraitConst, Vec3b};
+
+pub fn legacy_apply_color_change(original_image: &mut Mat, mask: &Mat, target_color: [u8; 3]) {
+    let target = Hsl::from(&Rgb::from(target_color));
+    for y in 0..original_image.rows() {
+        for x in 0..original_image.cols() {
+            if *mask.at_2d::<u8>(y, x).unwrap() == 255 {
+                let bgr_pixel = original_image.at_2d_mut::<Vec3b>(y, x).unwrap();
+                let rgba = Rgb::new(
+                    bgr_pixel[2] as f64,
+                    bgr_pixel[1] as f64,
+                    bgr_pixel[0] as f64,
+                    None,
+                );
+                let mut hsla: Hsl = rgba.as_ref().into();
+
+                let original_lightness = hsla.lightness();
+                let delta_lightness = target.lightness() - original_lightness;
+                hsla.set_hue(target.hue());
+                hsla.set_saturation(target.saturation());
+                hsla.set_lightness(original_lightness + non_linear_transform(delta_lightness));
+
+                let rgb_arr: [u8; 3] = Rgb::from(&hsla).into();
+                bgr_pixel[0] = rgb_arr[2];
+                bgr_pixel[1] = rgb_arr[1];
+                bgr_pixel[2] = rgb_arr[0];
+            }
+        }
+    }
+}
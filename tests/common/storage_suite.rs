@@ -0,0 +1,87 @@
+//! Storage checks shared by the Azurite and MinIO tests, which only differ in
+//! the `Storage` they run against. Each test gets a container of its own, so
+//! they can run in parallel; it is deleted again at the end.
+
+use carcaro::functionality::container_generation;
+use carcaro::storage::Storage;
+use carcaro::types::output::{OutputFormat, OutputOptions, VariantSize};
+use image::{DynamicImage, Rgb, RgbImage};
+
+pub async fn put_get_sign_list_and_delete(storage: Storage) {
+    storage
+        .ensure_container(storage.container(), false)
+        .await
+        .unwrap();
+
+    let key = format!("{}/renders/anonymous/1/full/0.png", storage.container());
+    storage
+        .put(&key, "image/png", b"not really a png".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(storage.get(&key).await.unwrap(), b"not really a png");
+
+    let listed = storage.list(storage.container(), "renders/").await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].key, key);
+    assert_eq!(listed[0].size, 16);
+
+    let signed = storage.signed_url(&key);
+    assert_ne!(signed, key, "key was not signed");
+    let response = reqwest::get(&signed).await.unwrap();
+    assert!(response.status().is_success(), "{}", response.status());
+    assert_eq!(
+        response.bytes().await.unwrap().as_ref(),
+        b"not really a png"
+    );
+
+    storage.delete(&key).await.unwrap();
+    assert!(storage
+        .list(storage.container(), "")
+        .await
+        .unwrap()
+        .is_empty());
+
+    storage.delete_container(storage.container()).await.unwrap();
+}
+
+pub async fn upload_and_remove_render(storage: Storage) {
+    storage
+        .ensure_container(storage.container(), false)
+        .await
+        .unwrap();
+
+    let frames: Vec<DynamicImage> = (0..3u8)
+        .map(|i| DynamicImage::ImageRgb8(RgbImage::from_pixel(64, 48, Rgb([i * 80, 20, 200]))))
+        .collect();
+    let output = OutputOptions {
+        format: OutputFormat::Png,
+        quality: 90,
+        sizes: vec![VariantSize::Thumbnail, VariantSize::Full],
+        sprite_sheet: Some(VariantSize::Thumbnail),
+    };
+    let prefix = container_generation::render_prefix(&storage, Some(7), 42);
+    let upload = container_generation::upload_frames(&storage, &prefix, frames, &output)
+        .await
+        .unwrap();
+    assert_eq!(upload.frames.len(), 3);
+    assert!(upload.sprite_sheet.is_some());
+
+    // 3 frames in 2 sizes, the sprite sheet and its manifest
+    let (_, path) = prefix.split_once('/').unwrap();
+    let listed = storage
+        .list(storage.container(), &format!("{}/", path))
+        .await
+        .unwrap();
+    assert_eq!(listed.len(), 8);
+
+    container_generation::remove_render(&storage, &prefix)
+        .await
+        .unwrap();
+    assert!(storage
+        .list(storage.container(), "")
+        .await
+        .unwrap()
+        .is_empty());
+
+    storage.delete_container(storage.container()).await.unwrap();
+}
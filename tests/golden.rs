@@ -0,0 +1,305 @@
+//! Golden-image regression tests for the color pipeline.
+//!
+//! Every base frame in `tests/fixtures/base` is masked with each mask profile
+//! and recolored offline with each finish below, including the texture and
+//! decal in `tests/fixtures/assets`, and the results are compared with the
+//! images in `tests/fixtures/golden` within a perceptual tolerance. Outputs that do not
+//! match are written to `target/golden-failures` for inspection.
+//!
+//! After an intentional change to the pipeline, re-bless the goldens with
+//!
+//!     BLESS_GOLDENS=1 cargo test --test golden
+
+use carcaro::functionality::color_swap::{
+    desired_areas, desired_areas_with_profile, recolor_frame,
+};
+use carcaro::functionality::mask::MaskProfile;
+use carcaro::types::decal::DecalPlacement;
+use carcaro::types::finish::Finish;
+use carcaro::types::gradient::{ColorStop, Gradient, GradientKind};
+use carcaro::types::texture::{Texture, TextureMode};
+use opencv::core::{Mat, MatTraitConst, Vector};
+use opencv::imgcodecs;
+use palette::color_difference::Ciede2000;
+use palette::{IntoColor, Lab, Srgb};
+use std::path::{Path, PathBuf};
+
+const BASE_DIR: &str = "tests/fixtures/base";
+const TEXTURE_PATH: &str = "tests/fixtures/assets/texture.png";
+const DECAL_PATH: &str = "tests/fixtures/assets/decal.png";
+const GOLDEN_DIR: &str = "tests/fixtures/golden";
+const FAILURE_DIR: &str = "target/golden-failures";
+
+/// Mean CIEDE2000 a recolored frame may drift from its golden. Anything below
+/// about 1 is not visible side by side.
+const MAX_MEAN_DELTA_E: f64 = 1.0;
+/// CIEDE2000 above which a single pixel counts as visibly different.
+const VISIBLE_DELTA_E: f32 = 5.0;
+/// Share of pixels, of frames and of masks, that may differ visibly, which
+/// leaves room for rounding along mask edges.
+const MAX_CHANGED_SHARE: f64 = 0.005;
+/// Difference in a mask value that counts as a changed pixel.
+const MASK_TOLERANCE: u8 = 32;
+
+fn blessing() -> bool {
+    std::env::var_os("BLESS_GOLDENS").is_some()
+}
+
+fn base_frames() -> Vec<PathBuf> {
+    let mut frames: Vec<PathBuf> = std::fs::read_dir(BASE_DIR)
+        .expect("Missing fixture base frames")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .map_or(false, |extension| extension == "png")
+        })
+        .collect();
+    frames.sort();
+    assert!(!frames.is_empty(), "No fixture base frames in {}", BASE_DIR);
+    frames
+}
+
+fn frame_name(path: &Path) -> String {
+    path.file_stem().unwrap().to_string_lossy().into_owned()
+}
+
+fn read(path: &Path, flags: i32) -> Mat {
+    let image = imgcodecs::imread(&path.to_string_lossy(), flags).unwrap();
+    assert!(!image.empty(), "Cannot read {}", path.display());
+    image
+}
+
+fn write(path: &Path, image: &Mat) {
+    std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+    imgcodecs::imwrite(&path.to_string_lossy(), image, &Vector::<i32>::new()).unwrap();
+}
+
+fn to_lab(bgr_pixel: &[u8]) -> Lab {
+    Srgb::new(bgr_pixel[2], bgr_pixel[1], bgr_pixel[0])
+        .into_format::<f32>()
+        .into_color()
+}
+
+/// Compares an output with its golden, or replaces the golden when blessing.
+/// Returns a description of the mismatch, if any.
+fn check_golden(
+    name: &str,
+    actual: &Mat,
+    flags: i32,
+    compare: fn(&Mat, &Mat) -> Result<(), String>,
+) -> Option<String> {
+    let golden_path = Path::new(GOLDEN_DIR).join(format!("{}.png", name));
+    if blessing() {
+        write(&golden_path, actual);
+        return None;
+    }
+    if !golden_path.exists() {
+        return Some(format!(
+            "{}: no golden image, run `BLESS_GOLDENS=1 cargo test --test golden` to create it",
+            name
+        ));
+    }
+
+    let golden = read(&golden_path, flags);
+    let result = if golden.size().unwrap() != actual.size().unwrap() {
+        Err(format!(
+            "size {:?} instead of {:?}",
+            actual.size().unwrap(),
+            golden.size().unwrap()
+        ))
+    } else {
+        compare(actual, &golden)
+    };
+    result.err().map(|mismatch| {
+        write(
+            &Path::new(FAILURE_DIR).join(format!("{}.png", name)),
+            actual,
+        );
+        format!("{}: {}", name, mismatch)
+    })
+}
+
+fn compare_frames(actual: &Mat, golden: &Mat) -> Result<(), String> {
+    let actual_data = actual.data_bytes().unwrap();
+    let golden_data = golden.data_bytes().unwrap();
+    let deltas: Vec<f32> = actual_data
+        .chunks_exact(3)
+        .zip(golden_data.chunks_exact(3))
+        .map(|(a, g)| to_lab(a).difference(to_lab(g)))
+        .collect();
+
+    let mean = deltas.iter().map(|&delta| delta as f64).sum::<f64>() / deltas.len() as f64;
+    let changed = deltas
+        .iter()
+        .filter(|&&delta| delta > VISIBLE_DELTA_E)
+        .count() as f64
+        / deltas.len() as f64;
+    if mean > MAX_MEAN_DELTA_E || changed > MAX_CHANGED_SHARE {
+        return Err(format!(
+            "mean CIEDE2000 {:.2}, {:.2}% of pixels visibly different",
+            mean,
+            changed * 100.0
+        ));
+    }
+    Ok(())
+}
+
+fn compare_masks(actual: &Mat, golden: &Mat) -> Result<(), String> {
+    let actual_data = actual.data_bytes().unwrap();
+    let golden_data = golden.data_bytes().unwrap();
+    let changed = actual_data
+        .iter()
+        .zip(golden_data)
+        .filter(|(a, g)| a.abs_diff(**g) > MASK_TOLERANCE)
+        .count() as f64
+        / actual_data.len() as f64;
+    if changed > MAX_CHANGED_SHARE {
+        return Err(format!("{:.2}% of mask pixels changed", changed * 100.0));
+    }
+    Ok(())
+}
+
+fn gradient() -> Gradient {
+    Gradient {
+        kind: GradientKind::Linear,
+        angle: 30.0,
+        center: [0.5, 0.5],
+        stops: vec![
+            ColorStop {
+                offset: 0.0,
+                color: [0, 82, 147],
+            },
+            ColorStop {
+                offset: 1.0,
+                color: [193, 18, 31],
+            },
+        ],
+    }
+}
+
+fn radial_gradient() -> Gradient {
+    Gradient {
+        kind: GradientKind::Radial,
+        angle: 0.0,
+        center: [0.3, 0.4],
+        stops: vec![
+            ColorStop {
+                offset: 0.0,
+                color: [236, 220, 245],
+            },
+            ColorStop {
+                offset: 0.6,
+                color: [0, 82, 147],
+            },
+            ColorStop {
+                offset: 1.0,
+                color: [10, 10, 10],
+            },
+        ],
+    }
+}
+
+/// Tiled at its own size, so the texture is not resampled.
+fn texture() -> Texture {
+    Texture {
+        url: TEXTURE_PATH.to_string(),
+        mode: TextureMode::Tile,
+        scale: 1.0,
+    }
+}
+
+/// Centered at its own size on the 192 pixel wide fixtures, so the decal
+/// is only moved, not resampled.
+fn decal() -> DecalPlacement {
+    DecalPlacement {
+        frame: 0,
+        url: DECAL_PATH.to_string(),
+        x: 0.5,
+        y: 0.5,
+        scale: 0.25,
+        rotation: 0.0,
+    }
+}
+
+/// The finishes and decals every fixture frame is rendered with, covering
+/// dark, light and saturated targets, both kinds of gradient, a texture and
+/// a decal over a solid finish.
+fn finishes() -> Vec<(&'static str, Finish, Vec<DecalPlacement>)> {
+    vec![
+        ("black", Finish::Solid([10, 10, 10]), vec![]),
+        ("pastel", Finish::Solid([236, 220, 245]), vec![]),
+        ("red", Finish::Solid([193, 18, 31]), vec![]),
+        ("gradient", Finish::Gradient(gradient().sorted()), vec![]),
+        (
+            "radial",
+            Finish::Gradient(radial_gradient().sorted()),
+            vec![],
+        ),
+        ("texture", Finish::Texture(texture()), vec![]),
+        ("decal", Finish::Solid([193, 18, 31]), vec![decal()]),
+    ]
+}
+
+fn assert_no_mismatches(mismatches: Vec<String>) {
+    assert!(
+        mismatches.is_empty(),
+        "Outputs differ from the goldens, see {}:\n{}",
+        FAILURE_DIR,
+        mismatches.join("\n")
+    );
+}
+
+#[test]
+fn masks_match_goldens() {
+    let profiles = [
+        ("mask", MaskProfile::Standard),
+        ("mask_wide", MaskProfile::Wide),
+        ("mask_strict", MaskProfile::Strict),
+    ];
+    let mut mismatches = Vec::new();
+    for path in base_frames() {
+        let frame = read(&path, imgcodecs::IMREAD_COLOR);
+        for (profile_name, profile) in profiles {
+            let mask = desired_areas_with_profile(&frame, profile).unwrap();
+            let name = format!("{}_{}", frame_name(&path), profile_name);
+            mismatches.extend(check_golden(
+                &name,
+                &mask,
+                imgcodecs::IMREAD_GRAYSCALE,
+                compare_masks,
+            ));
+        }
+    }
+    assert_no_mismatches(mismatches);
+}
+
+#[test]
+fn recolored_frames_match_goldens() {
+    let texture_image = read(Path::new(TEXTURE_PATH), imgcodecs::IMREAD_COLOR);
+    let decal_image = read(Path::new(DECAL_PATH), imgcodecs::IMREAD_UNCHANGED);
+    let mut mismatches = Vec::new();
+    for path in base_frames() {
+        let frame = read(&path, imgcodecs::IMREAD_COLOR);
+        let mask = desired_areas(&frame).unwrap();
+        for (finish_name, finish, decals) in finishes() {
+            let texture = match &finish {
+                Finish::Texture(_) => Some(&texture_image),
+                _ => None,
+            };
+            let decals: Vec<(DecalPlacement, Mat)> = decals
+                .into_iter()
+                .map(|decal| (decal, decal_image.try_clone().unwrap()))
+                .collect();
+            let mut recolored = frame.try_clone().unwrap();
+            recolor_frame(&mut recolored, &mask, &finish, texture, &decals).unwrap();
+            let name = format!("{}_{}", frame_name(&path), finish_name);
+            mismatches.extend(check_golden(
+                &name,
+                &recolored,
+                imgcodecs::IMREAD_COLOR,
+                compare_frames,
+            ));
+        }
+    }
+    assert_no_mismatches(mismatches);
+}
@@ -0,0 +1,42 @@
+//! Runs the S3 storage code path against a local MinIO. The tests are ignored
+//! by default; start the server and run them with
+//!
+//!     docker run -p 9000:9000 minio/minio server /data
+//!     CARCARO_S3_ACCESS_KEY_ID=minioadmin CARCARO_S3_SECRET_ACCESS_KEY=minioadmin \
+//!         cargo test --test minio -- --ignored
+//!
+//! `CARCARO_STORAGE_ENDPOINT` points them at another server.
+
+#[path = "common/storage_suite.rs"]
+mod storage_suite;
+
+use carcaro::config::{S3Config, StorageBackend, StorageConfig};
+use carcaro::storage::Storage;
+
+fn minio_storage() -> Storage {
+    let config = StorageConfig::from_env();
+    Storage::new(&StorageConfig {
+        backend: StorageBackend::S3,
+        container: format!("test-{}", uuid::Uuid::new_v4()),
+        endpoint: Some(
+            config
+                .endpoint
+                .clone()
+                .unwrap_or_else(|| "http://127.0.0.1:9000".to_string()),
+        ),
+        s3: S3Config::from_env(),
+        ..config
+    })
+}
+
+#[tokio::test]
+#[ignore = "needs MinIO"]
+async fn put_get_sign_list_and_delete() {
+    storage_suite::put_get_sign_list_and_delete(minio_storage()).await;
+}
+
+#[tokio::test]
+#[ignore = "needs MinIO"]
+async fn upload_and_remove_render() {
+    storage_suite::upload_and_remove_render(minio_storage()).await;
+}
@@ -0,0 +1,75 @@
+//! Checks that the row-slice HSL kernel of `apply_color_change` paints the
+//! same pixels as the `colorsys` kernel it replaced.
+
+#[path = "common/legacy_recolor.rs"]
+mod legacy_recolor;
+
+use carcaro::functionality::recolor::{apply_color_change, PaintTarget};
+use legacy_recolor::legacy_apply_color_change;
+use opencv::core::{Mat, MatTraitConst, Point, Scalar, Size, CV_8UC1, CV_8UC3};
+use opencv::imgproc;
+
+/// Largest difference in any channel of any pixel, which leaves room for the
+/// kernels rounding differently.
+const MAX_CHANNEL_DIFFERENCE: u8 = 1;
+
+/// Saturated, dark, light and grey targets, which take different branches of
+/// the HSL conversions.
+const TARGET_COLORS: [[u8; 3]; 5] = [
+    [200, 30, 45],
+    [30, 90, 200],
+    [20, 20, 20],
+    [240, 240, 235],
+    [128, 128, 128],
+];
+
+/// A frame of noise with a hard elliptical mask, so both kernels paint
+/// exactly the same pixels.
+fn synthetic_frame() -> (Mat, Mat) {
+    let mut image = Mat::new_rows_cols_with_default(240, 320, CV_8UC3, Scalar::all(0.0)).unwrap();
+    opencv::core::randu(&mut image, &Scalar::all(0.0), &Scalar::all(255.0)).unwrap();
+
+    let mut mask = Mat::new_rows_cols_with_default(240, 320, CV_8UC1, Scalar::all(0.0)).unwrap();
+    imgproc::ellipse(
+        &mut mask,
+        Point::new(160, 120),
+        Size::new(120, 60),
+        0.0,
+        0.0,
+        360.0,
+        Scalar::all(255.0),
+        imgproc::FILLED,
+        imgproc::LINE_8,
+        0,
+    )
+    .unwrap();
+    (image, mask)
+}
+
+#[test]
+fn row_slice_kernel_matches_colorsys_kernel() {
+    let (frame, mask) = synthetic_frame();
+    for target_color in TARGET_COLORS {
+        let mut expected = frame.try_clone().unwrap();
+        legacy_apply_color_change(&mut expected, &mask, target_color);
+
+        let mut actual = frame.try_clone().unwrap();
+        let target = PaintTarget::from(target_color);
+        apply_color_change(&mut actual, &mask, |_, _| target).unwrap();
+
+        let (expected_data, actual_data) =
+            (expected.data_bytes().unwrap(), actual.data_bytes().unwrap());
+        let worst = expected_data
+            .iter()
+            .zip(actual_data)
+            .map(|(&expected, &actual)| expected.abs_diff(actual))
+            .max()
+            .unwrap();
+        assert!(
+            worst <= MAX_CHANNEL_DIFFERENCE,
+            "Kernels differ by up to {} for target {:?}",
+            worst,
+            target_color
+        );
+    }
+}
@@ -0,0 +1,166 @@
+//! Runs a reconcile dry run against Azurite and a seeded Postgres. The test is
+//! ignored by default; start the emulator and point it at a scratch database
+//!
+//!     docker run -p 10000:10000 mcr.microsoft.com/azure-storage/azurite azurite-blob --blobHost 0.0.0.0
+//!     CARCARO_TEST_DATABASE_URL=postgres://... cargo test --test reconcile -- --ignored
+//!
+//! The database needs the `image` and `car` tables of the carcaro schema; the
+//! migrations run on connect. Everything the test seeds is removed again.
+
+use carcaro::config::{StorageBackend, StorageConfig};
+use carcaro::db::Connection;
+use carcaro::functionality::container_generation;
+use carcaro::functionality::reconcile::reconcile;
+use carcaro::storage::Storage;
+use carcaro::types::animation::{AnimationFormat, NewAnimation};
+use carcaro::types::image::{ImageId, NewImage};
+use carcaro::types::reconcile::ReconcileOptions;
+
+fn emulator_storage() -> Storage {
+    let container = format!("test-{}", uuid::Uuid::new_v4());
+    Storage::new(&StorageConfig {
+        backend: StorageBackend::Azure,
+        container,
+        emulator: true,
+        ..StorageConfig::from_env()
+    })
+}
+
+async fn put_frames(storage: &Storage, keys: &[String]) {
+    for key in keys {
+        storage
+            .put(key, "image/png", b"not really a png".to_vec())
+            .await
+            .unwrap();
+    }
+}
+
+async fn add_image(db: &Connection, url: Vec<String>, base_imageid: Option<i32>) -> ImageId {
+    let id = db.reserve_image_id().await.unwrap();
+    db.add_new_image(
+        id.clone(),
+        NewImage {
+            frame_count: url.len() as i32,
+            url,
+            colors: [200, 20, 20],
+            userid: None,
+            base_imageid,
+            variants: Vec::new(),
+            sprite_sheet: None,
+            accuracy: Vec::new(),
+        },
+    )
+    .await
+    .unwrap();
+    id
+}
+
+#[tokio::test]
+#[ignore = "needs Azurite and Postgres"]
+async fn dry_run_reports_without_deleting() {
+    let db_url = std::env::var("CARCARO_TEST_DATABASE_URL").expect(
+        "CARCARO_TEST_DATABASE_URL must point at a scratch database with the carcaro schema",
+    );
+    let db = Connection::new(&db_url).await;
+    let storage = emulator_storage();
+    storage
+        .ensure_container(storage.container(), false)
+        .await
+        .unwrap();
+    let container = storage.container().to_string();
+
+    // A base set with one of its two frames missing
+    let base_keys = vec![
+        format!("{}/base/0.png", container),
+        format!("{}/base/1.png", container),
+    ];
+    put_frames(&storage, &base_keys[..1]).await;
+    let base = add_image(&db, base_keys.clone(), None).await;
+
+    // A complete render, which is kept
+    let kept_prefix = container_generation::render_prefix(&storage, None, 900_001);
+    let kept_keys = vec![format!("{}/full/0.png", kept_prefix)];
+    put_frames(&storage, &kept_keys).await;
+    let kept = add_image(&db, kept_keys.clone(), Some(base.0)).await;
+
+    // A render with a deleted frame and an animation, which is removed
+    let broken_prefix = container_generation::render_prefix(&storage, None, 900_002);
+    let broken_keys = vec![
+        format!("{}/full/0.png", broken_prefix),
+        format!("{}/full/1.png", broken_prefix),
+    ];
+    put_frames(&storage, &broken_keys[..1]).await;
+    let broken = add_image(&db, broken_keys.clone(), Some(base.0)).await;
+    let animation_key = container_generation::upload_animation(
+        &storage,
+        &broken_prefix,
+        "gif",
+        "image/gif",
+        b"not really a gif".to_vec(),
+    )
+    .await
+    .unwrap();
+    db.add_animation(NewAnimation {
+        imageid: broken.clone(),
+        format: AnimationFormat::Gif,
+        url: animation_key.clone(),
+        width: 64,
+        height: 48,
+        delay_ms: 100,
+    })
+    .await
+    .unwrap();
+
+    // Frames of a render whose row was never written
+    let orphan_prefix = container_generation::render_prefix(&storage, None, 900_003);
+    let orphan_keys = vec![format!("{}/full/0.png", orphan_prefix)];
+    put_frames(&storage, &orphan_keys).await;
+
+    let report = reconcile(
+        &db,
+        &storage,
+        &ReconcileOptions {
+            dry_run: true,
+            anonymous_retention_days: None,
+            orphan_grace_hours: 0,
+        },
+    )
+    .await
+    .unwrap();
+
+    assert!(report.dry_run);
+    let base_missing = report
+        .missing_blobs
+        .iter()
+        .find(|missing| missing.imageid == base)
+        .expect("base set with a missing frame was not reported");
+    assert_eq!(base_missing.keys, vec![base_keys[1].clone()]);
+    assert!(!base_missing.removed);
+    let broken_missing = report
+        .missing_blobs
+        .iter()
+        .find(|missing| missing.imageid == broken)
+        .expect("render with a missing frame was not reported");
+    assert_eq!(broken_missing.keys, vec![broken_keys[1].clone()]);
+    assert!(broken_missing.removed);
+    assert!(report
+        .missing_blobs
+        .iter()
+        .all(|missing| missing.imageid != kept));
+    assert!(report.removed_objects.contains(&broken_keys[0]));
+    assert!(!report.removed_objects.contains(&kept_keys[0]));
+    assert!(!report.removed_objects.contains(&base_keys[0]));
+    assert!(report.removed_objects.contains(&animation_key));
+    assert!(report.orphaned_objects.contains(&orphan_keys[0]));
+    assert!(!report.orphaned_objects.contains(&kept_keys[0]));
+
+    // A dry run leaves rows and objects alone
+    db.get_image(broken.0).await.unwrap();
+    assert_eq!(db.get_animations(broken.0).await.unwrap().len(), 1);
+    let listed = storage.list(&container, "").await.unwrap();
+    assert_eq!(listed.len(), 5);
+    storage.get(&animation_key).await.unwrap();
+
+    db.delete_images(&[broken, kept, base]).await.unwrap();
+    storage.delete_container(&container).await.unwrap();
+}